@@ -4,6 +4,10 @@ use ic_cdk::update;
 use ic_cdk::query;
 use std::cell::RefCell;
 use serde::Serialize;
+use llm_providers::{LlmProvider, MockProvider, ProviderCall};
+
+// Canned tags until this canister gets real outcall plumbing
+const SIMULATED_TAGS: &str = "machine learning, decentralization, blockchain, AI agents, data sharing";
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 struct ResearchEntry {
@@ -13,7 +17,7 @@ struct ResearchEntry {
 }
 
 thread_local! {
-    static RESEARCH_ENTRIES: RefCell<Vec<ResearchEntry>> = RefCell::new(vec![]);
+    static RESEARCH_ENTRIES: RefCell<Vec<ResearchEntry>> = const { RefCell::new(vec![]) };
 }
 
 #[update]
@@ -38,7 +42,8 @@ async fn tag_last_entry() {
             entry.title, entry.abstract_body
         );
 
-        let tags = query_openai(prompt).await;
+        let provider = MockProvider::with_response(SIMULATED_TAGS);
+        let tags = query_llm_for_tags(&provider, prompt).await;
 
         RESEARCH_ENTRIES.with(|entries| {
             if let Some(last) = entries.borrow_mut().last_mut() {
@@ -48,15 +53,18 @@ async fn tag_last_entry() {
     }
 }
 
-async fn query_openai(_prompt: String) -> Vec<String> {
-    // Simulated OpenAI response
-    vec![
-        "machine learning".into(),
-        "decentralization".into(),
-        "blockchain".into(),
-        "AI agents".into(),
-        "data sharing".into()
-    ]
+async fn query_llm_for_tags(provider: &dyn LlmProvider, prompt: String) -> Vec<String> {
+    let content = match provider.prepare(&prompt, None) {
+        ProviderCall::Immediate(content) => content,
+        // Only providers answered without an outcall are usable here for now
+        ProviderCall::Http(_) => return vec![],
+    };
+
+    content
+        .split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
 }
 
 #[query]
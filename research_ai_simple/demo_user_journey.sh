@@ -187,6 +187,18 @@ compile_and_deploy() {
     if [ ! -z "$CANISTER_ID" ]; then
         print_success "Canister ID: $CANISTER_ID"
     fi
+    
+    # The Groq API key is not compiled in; hand it to the canister as a secret
+    if [ ! -z "$GROQ_API_KEY" ]; then
+        print_info "Setting Groq API key from GROQ_API_KEY..."
+        if ! dfx canister call $CANISTER_NAME set_provider_credentials "(\"$GROQ_API_KEY\")" > /dev/null; then
+            print_error "Failed to set provider credentials"
+            exit 1
+        fi
+        print_success "Provider credentials configured"
+    else
+        print_info "GROQ_API_KEY not set - news requests will fail until set_provider_credentials is called"
+    fi
 }
 
 # Function to launch Express.js UI
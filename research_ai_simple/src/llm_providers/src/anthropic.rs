@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{body_excerpt, classify};
+use crate::{
+    json_headers, max_response_bytes, ChatMessage, HttpCall, LlmError, LlmProvider, ProviderCall,
+    ProviderConfig,
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Ids, stop reason and usage around the completion
+const ENVELOPE_BYTES: u64 = 512;
+
+// Anthropic Messages API and compatible endpoints
+pub struct AnthropicProvider {
+    config: ProviderConfig,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessagesRequest<'a> {
+    pub model: &'a str,
+    pub messages: Vec<ChatMessage<'a>>,
+    pub max_tokens: u32,
+    pub temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MessagesResponse {
+    pub content: Vec<ContentBlock>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContentBlock {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorBody {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub message: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        AnthropicProvider { config }
+    }
+}
+
+impl LlmProvider for AnthropicProvider {
+    fn config(&self) -> &ProviderConfig {
+        &self.config
+    }
+
+    fn prepare(&self, prompt: &str, api_key: Option<&str>) -> ProviderCall {
+        let request = MessagesRequest {
+            model: &self.config.model,
+            messages: vec![ChatMessage::user(prompt)],
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+        };
+
+        let mut extra = vec![("anthropic-version".to_string(), ANTHROPIC_VERSION.to_string())];
+        if let Some(key) = api_key {
+            extra.push(("x-api-key".to_string(), key.to_string()));
+        }
+
+        ProviderCall::Http(HttpCall {
+            url: format!("{}/messages", self.config.base_url()),
+            headers: json_headers(extra),
+            body: serde_json::to_vec(&request).expect("messages request is always serializable"),
+            max_response_bytes: max_response_bytes(self.config.max_tokens, ENVELOPE_BYTES),
+        })
+    }
+
+    fn parse_response(&self, status: u16, body: &[u8]) -> Result<String, LlmError> {
+        if let Ok(error) = serde_json::from_slice::<ErrorResponse>(body) {
+            let kind = Some(error.error.kind.as_str());
+            return Err(classify(status, kind, error.error.message, &self.config.model));
+        }
+        if !(200..300).contains(&status) {
+            return Err(classify(status, None, body_excerpt(body), &self.config.model));
+        }
+
+        let response: MessagesResponse = serde_json::from_slice(body)
+            .map_err(|e| LlmError::InvalidResponse { message: e.to_string() })?;
+        let text: Vec<String> = response.content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .filter_map(|block| block.text)
+            .collect();
+        if text.is_empty() {
+            return Err(LlmError::InvalidResponse {
+                message: "response contained no text content".to_string(),
+            });
+        }
+        Ok(text.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_call, request_json, AWKWARD_PROMPT};
+    use crate::ProviderKind;
+
+    // Error bodies as the Messages API returns them
+    const RATE_LIMIT: &str = r#"{"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your per-minute rate limit (https://docs.anthropic.com/en/api/rate-limits); see the response headers for current usage."}}"#;
+    const BAD_KEY: &str = r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#;
+    const UNKNOWN_MODEL: &str = r#"{"type":"error","error":{"type":"not_found_error","message":"model: claude-3-5-haiku-latest"}}"#;
+
+    fn provider() -> AnthropicProvider {
+        AnthropicProvider::new(ProviderConfig::for_kind(ProviderKind::Anthropic))
+    }
+
+    #[test]
+    fn prompts_round_trip_through_the_request_body() {
+        let json = request_json(&provider(), AWKWARD_PROMPT);
+        assert_eq!(json["messages"][0]["content"], AWKWARD_PROMPT);
+        assert_eq!(json["max_tokens"], 300);
+    }
+
+    #[test]
+    fn classifies_anthropic_error_bodies() {
+        let provider = provider();
+        assert!(matches!(
+            provider.parse_response(429, RATE_LIMIT.as_bytes()),
+            Err(LlmError::RateLimited { .. })
+        ));
+        assert_eq!(
+            provider.parse_response(401, BAD_KEY.as_bytes()),
+            Err(LlmError::Unauthorized { message: "invalid x-api-key".to_string() })
+        );
+        assert!(matches!(
+            provider.parse_response(404, UNKNOWN_MODEL.as_bytes()),
+            Err(LlmError::ModelNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn sends_version_and_key_headers_and_joins_text_blocks() {
+        let provider = provider();
+        let call = http_call(&provider, "hi", Some("key"));
+        assert_eq!(call.url, "https://api.anthropic.com/v1/messages");
+        assert!(call.headers.contains(&("anthropic-version".to_string(), ANTHROPIC_VERSION.to_string())));
+        assert!(call.headers.contains(&("x-api-key".to_string(), "key".to_string())));
+
+        let body = br#"{"id":"msg_01","type":"message","role":"assistant","content":[{"type":"text","text":"First"},{"type":"tool_use","id":"t"},{"type":"text","text":"Second"}],"stop_reason":"end_turn"}"#;
+        assert_eq!(provider.parse_response(200, body), Ok("First\nSecond".to_string()));
+    }
+}
@@ -0,0 +1,127 @@
+use candid::{CandidType, Deserialize};
+use std::fmt;
+
+// Why a completion could not be produced
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum LlmError {
+    // 429 or a provider rate-limit error
+    RateLimited { message: String },
+    // Missing, invalid or revoked API key
+    Unauthorized { message: String },
+    ModelNotFound { model: String, message: String },
+    MissingCredentials { provider: String },
+    // Any other error status or error body from the provider
+    Api { status: u16, message: String },
+    // A 2xx response we could not make sense of
+    InvalidResponse { message: String },
+    // The outcall itself was rejected before any response arrived
+    Transport { code: String, message: String },
+    // Not attempted: the provider has been failing and calls to it are
+    // paused until `retry_at`
+    CircuitOpen { provider: String, retry_at: u64 },
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::RateLimited { message } => write!(f, "rate limited: {}", message),
+            LlmError::Unauthorized { message } => write!(f, "unauthorized: {}", message),
+            LlmError::ModelNotFound { model, message } => {
+                write!(f, "model '{}' not found: {}", model, message)
+            }
+            LlmError::MissingCredentials { provider } => {
+                write!(f, "no {} API key configured (see set_provider_credentials)", provider)
+            }
+            LlmError::Api { status, message } => write!(f, "API error {}: {}", status, message),
+            LlmError::InvalidResponse { message } => write!(f, "invalid response: {}", message),
+            LlmError::Transport { code, message } => write!(f, "outcall failed ({}): {}", code, message),
+            LlmError::CircuitOpen { provider, retry_at } => {
+                write!(f, "{} calls paused after repeated errors until {}", provider, retry_at)
+            }
+        }
+    }
+}
+
+impl LlmError {
+    // Short machine-readable code: the rejection code for failed outcalls,
+    // otherwise the error class
+    pub fn code(&self) -> String {
+        match self {
+            LlmError::RateLimited { .. } => "rate_limited".to_string(),
+            LlmError::Unauthorized { .. } => "unauthorized".to_string(),
+            LlmError::ModelNotFound { .. } => "model_not_found".to_string(),
+            LlmError::MissingCredentials { .. } => "missing_credentials".to_string(),
+            LlmError::Api { status, .. } => format!("http_{}", status),
+            LlmError::InvalidResponse { .. } => "invalid_response".to_string(),
+            LlmError::Transport { code, .. } => code.clone(),
+            LlmError::CircuitOpen { .. } => "circuit_open".to_string(),
+        }
+    }
+}
+
+// Map an error status plus whatever the provider said about it onto a
+// structured error. `kind` is the provider's machine-readable error code or
+// type, when it sends one.
+pub(crate) fn classify(status: u16, kind: Option<&str>, message: String, model: &str) -> LlmError {
+    let kind = kind.unwrap_or_default();
+    if status == 429 || kind.contains("rate_limit") {
+        LlmError::RateLimited { message }
+    } else if status == 401
+        || status == 403
+        || kind.contains("invalid_api_key")
+        || kind.contains("authentication")
+    {
+        LlmError::Unauthorized { message }
+    } else if kind == "model_not_found"
+        || kind == "not_found_error"
+        || (status == 404 && message.contains("model"))
+    {
+        LlmError::ModelNotFound { model: model.to_string(), message }
+    } else {
+        LlmError::Api { status, message }
+    }
+}
+
+// Trim a raw body down to something worth storing in an error
+pub(crate) fn body_excerpt(body: &[u8]) -> String {
+    const MAX_EXCERPT_CHARS: usize = 200;
+    String::from_utf8_lossy(body).chars().take(MAX_EXCERPT_CHARS).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_decides_when_the_provider_sends_no_kind() {
+        let classify = |status| classify(status, None, "nope".to_string(), "llama3");
+        assert!(matches!(classify(429), LlmError::RateLimited { .. }));
+        assert!(matches!(classify(401), LlmError::Unauthorized { .. }));
+        assert!(matches!(classify(403), LlmError::Unauthorized { .. }));
+        assert_eq!(classify(500), LlmError::Api { status: 500, message: "nope".to_string() });
+    }
+
+    #[test]
+    fn kind_decides_when_the_status_is_generic() {
+        assert!(matches!(
+            classify(400, Some("rate_limit_exceeded"), String::new(), "m"),
+            LlmError::RateLimited { .. }
+        ));
+        assert!(matches!(
+            classify(400, Some("invalid_api_key"), String::new(), "m"),
+            LlmError::Unauthorized { .. }
+        ));
+        assert_eq!(
+            classify(400, Some("model_not_found"), "gone".to_string(), "m"),
+            LlmError::ModelNotFound { model: "m".to_string(), message: "gone".to_string() }
+        );
+        // A 404 only means a missing model when the message says so
+        assert!(matches!(classify(404, None, "no route".to_string(), "m"), LlmError::Api { .. }));
+    }
+
+    #[test]
+    fn excerpts_are_cut_on_character_boundaries() {
+        let body = "\u{e9}".repeat(300);
+        assert_eq!(body_excerpt(body.as_bytes()).chars().count(), 200);
+    }
+}
@@ -0,0 +1,226 @@
+// Shared LLM provider abstraction. This crate only knows how to shape a
+// chat request for each provider and read the answer back out; canisters
+// execute the HTTP call with whatever ic-cdk version they are built on.
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+mod anthropic;
+mod error;
+mod mock;
+mod ollama;
+mod openai;
+mod transform;
+
+pub use anthropic::AnthropicProvider;
+pub use error::LlmError;
+pub use mock::MockProvider;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAiCompatibleProvider;
+pub use transform::normalize_response_body;
+
+// Largest completion a config may ask for
+pub const MAX_TOKENS: u32 = 8192;
+// A token is a few bytes of text, more once it is JSON-escaped
+const BYTES_PER_TOKEN: u64 = 8;
+// Response headers count against the outcall's response limit too
+const RESPONSE_HEADER_BYTES: u64 = 2048;
+
+// Most a replica will fetch for one outcall
+const MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+// Upper bound on the response we ask the replica to fetch: room for
+// `max_tokens` of content plus the provider's JSON around it
+fn max_response_bytes(max_tokens: u32, envelope_bytes: u64) -> u64 {
+    (RESPONSE_HEADER_BYTES + envelope_bytes + max_tokens as u64 * BYTES_PER_TOKEN).min(MAX_RESPONSE_BYTES)
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ProviderKind {
+    Groq,
+    OpenAi,
+    Anthropic,
+    Ollama,
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProviderKind::Groq => "groq",
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Mock => "mock",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            ProviderKind::Groq => "llama3-8b-8192",
+            ProviderKind::OpenAi => "gpt-4o-mini",
+            ProviderKind::Anthropic => "claude-3-5-haiku-latest",
+            ProviderKind::Ollama => "llama3",
+            ProviderKind::Mock => "mock",
+        }
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            ProviderKind::Groq => "https://api.groq.com/openai/v1",
+            ProviderKind::OpenAi => "https://api.openai.com/v1",
+            ProviderKind::Anthropic => "https://api.anthropic.com/v1",
+            ProviderKind::Ollama => "http://localhost:11434",
+            ProviderKind::Mock => "",
+        }
+    }
+
+    // Whether calls to this provider need an API key
+    pub fn requires_api_key(&self) -> bool {
+        !matches!(self, ProviderKind::Ollama | ProviderKind::Mock)
+    }
+}
+
+// Which provider to use and how to call it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProviderConfig {
+    pub kind: ProviderKind,
+    pub model: String,
+    pub max_tokens: u32,
+    pub temperature: f64,
+    // Overrides the provider's default API root, e.g. a self-hosted Ollama
+    pub base_url: Option<String>,
+}
+
+impl ProviderConfig {
+    pub fn for_kind(kind: ProviderKind) -> Self {
+        ProviderConfig {
+            kind,
+            model: kind.default_model().to_string(),
+            max_tokens: 300,
+            temperature: 0.7,
+            base_url: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.model.trim().is_empty() {
+            return Err("model must not be empty".to_string());
+        }
+        if self.max_tokens == 0 || self.max_tokens > MAX_TOKENS {
+            return Err(format!("max_tokens must be between 1 and {}", MAX_TOKENS));
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err("temperature must be between 0.0 and 2.0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn base_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or_else(|| self.kind.default_base_url())
+            .trim_end_matches('/')
+    }
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        ProviderConfig::for_kind(ProviderKind::Groq)
+    }
+}
+
+// A POST request ready to be handed to the canister HTTP outcall API
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpCall {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub max_response_bytes: u64,
+}
+
+// What the caller has to do to get a completion
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProviderCall {
+    Http(HttpCall),
+    // Answered without any outcall (mock provider)
+    Immediate(String),
+}
+
+pub trait LlmProvider {
+    fn config(&self) -> &ProviderConfig;
+
+    // Shape a single-turn chat request for `prompt`
+    fn prepare(&self, prompt: &str, api_key: Option<&str>) -> ProviderCall;
+
+    // Pull the completion text out of a response, telling provider errors
+    // (rate limits, bad keys, unknown models) apart from real content
+    fn parse_response(&self, status: u16, body: &[u8]) -> Result<String, LlmError>;
+}
+
+// One chat turn, shared by every chat-style request body
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChatMessage<'a> {
+    pub role: &'a str,
+    pub content: &'a str,
+}
+
+impl<'a> ChatMessage<'a> {
+    pub fn user(content: &'a str) -> Self {
+        ChatMessage { role: "user", content }
+    }
+}
+
+pub fn provider_for(config: &ProviderConfig) -> Box<dyn LlmProvider> {
+    match config.kind {
+        ProviderKind::Groq | ProviderKind::OpenAi => {
+            Box::new(OpenAiCompatibleProvider::new(config.clone()))
+        }
+        ProviderKind::Anthropic => Box::new(AnthropicProvider::new(config.clone())),
+        ProviderKind::Ollama => Box::new(OllamaProvider::new(config.clone())),
+        ProviderKind::Mock => Box::new(MockProvider::new(config.clone())),
+    }
+}
+
+fn json_headers(extra: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    headers.extend(extra);
+    headers
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Everything JSON has to escape, plus multi-byte text
+    pub(crate) const AWKWARD_PROMPT: &str =
+        "Say \"hi\" to C:\\news\\lagos\nthen\ttab\r\u{1}\u{7f} and caf\u{e9} \u{1f4f0}";
+
+    // Groq's reply once a model's token rate limit is used up, as recorded
+    pub(crate) const GROQ_RATE_LIMIT: &str = r#"{"error":{"message":"Rate limit reached for model `llama3-8b-8192` in organization `org_01hq` on tokens per minute (TPM): Limit 30000, Used 29870, Requested 358. Please try again in 456ms.","type":"tokens","code":"rate_limit_exceeded"}}"#;
+
+    pub(crate) fn http_call(provider: &dyn LlmProvider, prompt: &str, api_key: Option<&str>) -> HttpCall {
+        match provider.prepare(prompt, api_key) {
+            ProviderCall::Http(call) => call,
+            ProviderCall::Immediate(_) => panic!("expected an outcall"),
+        }
+    }
+
+    // The request body parsed back, so tests check what the provider sees
+    pub(crate) fn request_json(provider: &dyn LlmProvider, prompt: &str) -> serde_json::Value {
+        serde_json::from_slice(&http_call(provider, prompt, None).body).expect("request body is valid JSON")
+    }
+
+    #[test]
+    fn response_limit_grows_with_max_tokens() {
+        for kind in [ProviderKind::Groq, ProviderKind::Anthropic, ProviderKind::Ollama] {
+            let small = ProviderConfig::for_kind(kind);
+            let large = ProviderConfig { max_tokens: MAX_TOKENS, ..small.clone() };
+            let limit = |config: &ProviderConfig| http_call(&*provider_for(config), "prompt", None).max_response_bytes;
+            assert!(limit(&small) > small.max_tokens as u64 * BYTES_PER_TOKEN + RESPONSE_HEADER_BYTES);
+            assert!(limit(&large) > MAX_TOKENS as u64 * BYTES_PER_TOKEN);
+            assert!(limit(&large) <= MAX_RESPONSE_BYTES);
+        }
+        let config = ProviderConfig { max_tokens: MAX_TOKENS + 1, ..ProviderConfig::default() };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{LlmError, LlmProvider, ProviderCall, ProviderConfig, ProviderKind};
+
+// Deterministic provider for local development and tests: never makes an
+// outcall and always gives the same answer for the same prompt.
+pub struct MockProvider {
+    config: ProviderConfig,
+    response: Option<String>,
+}
+
+impl MockProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        MockProvider { config, response: None }
+    }
+
+    // A mock that answers every prompt with `response`
+    pub fn with_response(response: impl Into<String>) -> Self {
+        MockProvider {
+            config: ProviderConfig::for_kind(ProviderKind::Mock),
+            response: Some(response.into()),
+        }
+    }
+}
+
+impl LlmProvider for MockProvider {
+    fn config(&self) -> &ProviderConfig {
+        &self.config
+    }
+
+    fn prepare(&self, prompt: &str, _api_key: Option<&str>) -> ProviderCall {
+        let content = match &self.response {
+            Some(response) => response.clone(),
+            None => format!(
+                "[mock:{}] {} words, {} characters: {}",
+                self.config.model,
+                prompt.split_whitespace().count(),
+                prompt.chars().count(),
+                prompt.chars().take(self.config.max_tokens as usize).collect::<String>()
+            ),
+        };
+        ProviderCall::Immediate(content)
+    }
+
+    fn parse_response(&self, _status: u16, body: &[u8]) -> Result<String, LlmError> {
+        Ok(String::from_utf8_lossy(body).to_string())
+    }
+}
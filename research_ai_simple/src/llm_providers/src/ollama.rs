@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{body_excerpt, classify};
+use crate::{
+    json_headers, max_response_bytes, ChatMessage, HttpCall, LlmError, LlmProvider, ProviderCall,
+    ProviderConfig,
+};
+
+// Model, timings and token counts around the completion
+const ENVELOPE_BYTES: u64 = 512;
+
+// Ollama's native `/api/chat` endpoint. Replicas only make HTTPS outcalls,
+// so on a real subnet `base_url` must point at a TLS-fronted host.
+pub struct OllamaProvider {
+    config: ProviderConfig,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OllamaChatRequest<'a> {
+    pub model: &'a str,
+    pub messages: Vec<ChatMessage<'a>>,
+    pub stream: bool,
+    pub options: OllamaOptions,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OllamaOptions {
+    pub num_predict: u32,
+    pub temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaChatResponse {
+    pub message: OllamaMessage,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaMessage {
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaError {
+    pub error: String,
+}
+
+impl OllamaProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        OllamaProvider { config }
+    }
+}
+
+impl LlmProvider for OllamaProvider {
+    fn config(&self) -> &ProviderConfig {
+        &self.config
+    }
+
+    fn prepare(&self, prompt: &str, api_key: Option<&str>) -> ProviderCall {
+        let request = OllamaChatRequest {
+            model: &self.config.model,
+            messages: vec![ChatMessage::user(prompt)],
+            stream: false,
+            options: OllamaOptions {
+                num_predict: self.config.max_tokens,
+                temperature: self.config.temperature,
+            },
+        };
+
+        // Plain Ollama has no auth, but proxies in front of it often do
+        let auth = api_key
+            .map(|key| vec![("Authorization".to_string(), format!("Bearer {}", key))])
+            .unwrap_or_default();
+
+        ProviderCall::Http(HttpCall {
+            url: format!("{}/api/chat", self.config.base_url()),
+            headers: json_headers(auth),
+            body: serde_json::to_vec(&request).expect("chat request is always serializable"),
+            max_response_bytes: max_response_bytes(self.config.max_tokens, ENVELOPE_BYTES),
+        })
+    }
+
+    fn parse_response(&self, status: u16, body: &[u8]) -> Result<String, LlmError> {
+        if let Ok(error) = serde_json::from_slice::<OllamaError>(body) {
+            let status = if status < 400 { 500 } else { status };
+            return Err(classify(status, None, error.error, &self.config.model));
+        }
+        if !(200..300).contains(&status) {
+            return Err(classify(status, None, body_excerpt(body), &self.config.model));
+        }
+
+        serde_json::from_slice::<OllamaChatResponse>(body)
+            .map(|response| response.message.content)
+            .map_err(|e| LlmError::InvalidResponse { message: e.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_call, request_json, AWKWARD_PROMPT};
+    use crate::ProviderKind;
+
+    fn provider() -> OllamaProvider {
+        OllamaProvider::new(ProviderConfig::for_kind(ProviderKind::Ollama))
+    }
+
+    #[test]
+    fn prompts_round_trip_through_the_request_body() {
+        let json = request_json(&provider(), AWKWARD_PROMPT);
+        assert_eq!(json["messages"][0]["content"], AWKWARD_PROMPT);
+        assert_eq!(json["stream"], false);
+    }
+
+    #[test]
+    fn classifies_ollama_error_bodies() {
+        let provider = provider();
+        let unknown_model = br#"{"error":"model \"llama3\" not found, try pulling it first"}"#;
+        assert!(matches!(
+            provider.parse_response(404, unknown_model),
+            Err(LlmError::ModelNotFound { model, .. }) if model == "llama3"
+        ));
+        // Errors sent with a success status still count as errors
+        assert!(matches!(
+            provider.parse_response(200, br#"{"error":"unexpected EOF"}"#),
+            Err(LlmError::Api { status: 500, .. })
+        ));
+    }
+
+    #[test]
+    fn calls_api_chat_without_auth_and_reads_the_message() {
+        let provider = provider();
+        let call = http_call(&provider, "hi", None);
+        assert_eq!(call.url, "http://localhost:11434/api/chat");
+        assert!(!call.headers.iter().any(|(name, _)| name == "Authorization"));
+        assert_eq!(request_json(&provider, "hi")["options"]["num_predict"], 300);
+
+        let body = br#"{"model":"llama3","created_at":"2024-06-25T10:00:00Z","message":{"role":"assistant","content":"Hello"},"done":true}"#;
+        assert_eq!(provider.parse_response(200, body), Ok("Hello".to_string()));
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{body_excerpt, classify};
+use crate::{
+    json_headers, max_response_bytes, ChatMessage, HttpCall, LlmError, LlmProvider, ProviderCall,
+    ProviderConfig,
+};
+
+// Ids, usage and timing fields around the completion (Groq adds more)
+const ENVELOPE_BYTES: u64 = 1024;
+
+// Groq, OpenAI and anything else speaking `/chat/completions`
+pub struct OpenAiCompatibleProvider {
+    config: ProviderConfig,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatCompletionRequest<'a> {
+    pub model: &'a str,
+    pub messages: Vec<ChatMessage<'a>>,
+    pub max_tokens: u32,
+    pub temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionResponse {
+    pub choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatChoice {
+    pub message: ChatResponseMessage,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatResponseMessage {
+    pub content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiErrorResponse {
+    pub error: ApiErrorBody,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiErrorBody {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub code: Option<String>,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        OpenAiCompatibleProvider { config }
+    }
+}
+
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn config(&self) -> &ProviderConfig {
+        &self.config
+    }
+
+    fn prepare(&self, prompt: &str, api_key: Option<&str>) -> ProviderCall {
+        let request = ChatCompletionRequest {
+            model: &self.config.model,
+            messages: vec![ChatMessage::user(prompt)],
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+        };
+
+        let auth = api_key
+            .map(|key| vec![("Authorization".to_string(), format!("Bearer {}", key))])
+            .unwrap_or_default();
+
+        ProviderCall::Http(HttpCall {
+            url: format!("{}/chat/completions", self.config.base_url()),
+            headers: json_headers(auth),
+            body: serde_json::to_vec(&request).expect("chat request is always serializable"),
+            max_response_bytes: max_response_bytes(self.config.max_tokens, ENVELOPE_BYTES),
+        })
+    }
+
+    fn parse_response(&self, status: u16, body: &[u8]) -> Result<String, LlmError> {
+        if let Ok(error) = serde_json::from_slice::<ApiErrorResponse>(body) {
+            let kind = error.error.code.as_deref().or(error.error.kind.as_deref());
+            return Err(classify(status, kind, error.error.message, &self.config.model));
+        }
+        if !(200..300).contains(&status) {
+            return Err(classify(status, None, body_excerpt(body), &self.config.model));
+        }
+
+        let response: ChatCompletionResponse = serde_json::from_slice(body)
+            .map_err(|e| LlmError::InvalidResponse { message: e.to_string() })?;
+        response.choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| LlmError::InvalidResponse {
+                message: "response contained no message content".to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_call, request_json, AWKWARD_PROMPT, GROQ_RATE_LIMIT};
+    use crate::ProviderKind;
+
+    // Error bodies as Groq returns them
+    const GROQ_BAD_KEY: &str = r#"{"error":{"message":"Invalid API Key","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+    const GROQ_UNKNOWN_MODEL: &str = r#"{"error":{"message":"The model `llama3-70b` does not exist or you do not have access to it.","type":"invalid_request_error","code":"model_not_found"}}"#;
+
+    fn groq() -> OpenAiCompatibleProvider {
+        OpenAiCompatibleProvider::new(ProviderConfig::for_kind(ProviderKind::Groq))
+    }
+
+    #[test]
+    fn prompts_round_trip_through_the_request_body() {
+        let json = request_json(&groq(), AWKWARD_PROMPT);
+        assert_eq!(json["messages"][0]["content"], AWKWARD_PROMPT);
+        assert_eq!(json["messages"][0]["role"], "user");
+        assert_eq!(json["model"], "llama3-8b-8192");
+    }
+
+    #[test]
+    fn classifies_groq_error_bodies() {
+        let provider = groq();
+        assert!(matches!(
+            provider.parse_response(429, GROQ_RATE_LIMIT.as_bytes()),
+            Err(LlmError::RateLimited { .. })
+        ));
+        assert_eq!(
+            provider.parse_response(401, GROQ_BAD_KEY.as_bytes()),
+            Err(LlmError::Unauthorized { message: "Invalid API Key".to_string() })
+        );
+        assert!(matches!(
+            provider.parse_response(404, GROQ_UNKNOWN_MODEL.as_bytes()),
+            Err(LlmError::ModelNotFound { model, .. }) if model == "llama3-8b-8192"
+        ));
+    }
+
+    #[test]
+    fn targets_chat_completions_with_a_bearer_key_and_reads_the_first_choice() {
+        let config = ProviderConfig {
+            base_url: Some("https://llm.example.com/v1/".to_string()),
+            ..ProviderConfig::for_kind(ProviderKind::OpenAi)
+        };
+        let provider = OpenAiCompatibleProvider::new(config);
+        let call = http_call(&provider, "hi", Some("sk-test"));
+        assert_eq!(call.url, "https://llm.example.com/v1/chat/completions");
+        assert!(call.headers.contains(&("Authorization".to_string(), "Bearer sk-test".to_string())));
+
+        let body = br#"{"choices":[{"index":0,"message":{"role":"assistant","content":"Hello"},"finish_reason":"stop"}]}"#;
+        assert_eq!(provider.parse_response(200, body), Ok("Hello".to_string()));
+        assert!(matches!(
+            provider.parse_response(200, br#"{"choices":[]}"#),
+            Err(LlmError::InvalidResponse { .. })
+        ));
+    }
+}
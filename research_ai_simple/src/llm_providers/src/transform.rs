@@ -0,0 +1,108 @@
+// Response normalization for HTTP outcall consensus. Every replica fetches
+// the response independently, so anything that differs per request (ids,
+// timestamps, token accounting) has to go before the replicas compare bodies.
+use serde_json::Value;
+
+// Top-level fields providers fill with per-request values
+const NON_DETERMINISTIC_FIELDS: &[&str] = &[
+    // OpenAI / Groq
+    "id",
+    "created",
+    "usage",
+    "system_fingerprint",
+    "x_groq",
+    // Ollama
+    "created_at",
+    "total_duration",
+    "load_duration",
+    "prompt_eval_count",
+    "prompt_eval_duration",
+    "eval_count",
+    "eval_duration",
+];
+
+// Per-choice fields that vary between otherwise identical completions
+const NON_DETERMINISTIC_CHOICE_FIELDS: &[&str] = &["logprobs"];
+
+// Strip non-deterministic fields from a JSON body and re-serialize it with
+// sorted keys. Bodies that aren't JSON are passed through untouched.
+pub fn normalize_response_body(body: &[u8]) -> Vec<u8> {
+    let Ok(mut json) = serde_json::from_slice::<Value>(body) else {
+        return body.to_vec();
+    };
+
+    if let Value::Object(fields) = &mut json {
+        for field in NON_DETERMINISTIC_FIELDS {
+            fields.remove(*field);
+        }
+        if let Some(Value::Object(error)) = fields.get_mut("error") {
+            // Some providers put a request id inside error bodies too
+            error.remove("request_id");
+        }
+        if let Some(Value::Array(choices)) = fields.get_mut("choices") {
+            for choice in choices.iter_mut().filter_map(Value::as_object_mut) {
+                for field in NON_DETERMINISTIC_CHOICE_FIELDS {
+                    choice.remove(*field);
+                }
+            }
+        }
+    }
+
+    serde_json::to_vec(&json).expect("a parsed JSON value always serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::GROQ_RATE_LIMIT;
+
+    // Two replicas' view of the same Groq completion, as recorded
+    const GROQ_RESPONSE_A: &str = r#"{"id":"chatcmpl-8a1f4b7e-2c1d-4a55-9f63-0b2d1a9e7c11","object":"chat.completion","created":1719313112,"model":"llama3-8b-8192","choices":[{"index":0,"message":{"role":"assistant","content":"Here are the latest updates on the Lagos tech scene:\n\n1) Recent headlines..."},"logprobs":null,"finish_reason":"stop"}],"usage":{"queue_time":0.011204,"prompt_tokens":58,"prompt_time":0.004563,"completion_tokens":300,"completion_time":0.24,"total_tokens":358,"total_time":0.244563},"system_fingerprint":"fp_af05557ca2","x_groq":{"id":"req_01j16b0w2mfv5r6e9q1w7tn2k3"}}"#;
+    const GROQ_RESPONSE_B: &str = r#"{"id":"chatcmpl-3e9d0c55-7b4f-4f0e-8d2a-5c6b7a8f9e01","object":"chat.completion","created":1719313113,"model":"llama3-8b-8192","choices":[{"index":0,"message":{"role":"assistant","content":"Here are the latest updates on the Lagos tech scene:\n\n1) Recent headlines..."},"logprobs":null,"finish_reason":"stop"}],"usage":{"queue_time":0.019871,"prompt_tokens":58,"prompt_time":0.004112,"completion_tokens":300,"completion_time":0.25,"total_tokens":358,"total_time":0.254112},"system_fingerprint":"fp_c4a72fb330","x_groq":{"id":"req_01j16b0w9c2ht3m8w4zq5d6e7f"}}"#;
+
+    #[test]
+    fn responses_from_different_replicas_normalize_to_the_same_bytes() {
+        assert_ne!(GROQ_RESPONSE_A, GROQ_RESPONSE_B);
+        assert_eq!(
+            normalize_response_body(GROQ_RESPONSE_A.as_bytes()),
+            normalize_response_body(GROQ_RESPONSE_B.as_bytes())
+        );
+    }
+
+    #[test]
+    fn strips_non_deterministic_fields_and_keeps_content() {
+        let normalized = normalize_response_body(GROQ_RESPONSE_A.as_bytes());
+        let json: Value = serde_json::from_slice(&normalized).unwrap();
+
+        for field in ["id", "created", "usage", "system_fingerprint", "x_groq"] {
+            assert!(json.get(field).is_none(), "{} should have been removed", field);
+        }
+        assert!(json["choices"][0].get("logprobs").is_none());
+        assert_eq!(json["model"], "llama3-8b-8192");
+        assert_eq!(json["choices"][0]["finish_reason"], "stop");
+        assert_eq!(
+            json["choices"][0]["message"]["content"],
+            "Here are the latest updates on the Lagos tech scene:\n\n1) Recent headlines..."
+        );
+    }
+
+    #[test]
+    fn normalization_is_idempotent() {
+        let once = normalize_response_body(GROQ_RESPONSE_A.as_bytes());
+        assert_eq!(normalize_response_body(&once), once);
+    }
+
+    #[test]
+    fn error_bodies_survive_normalization() {
+        let normalized = normalize_response_body(GROQ_RATE_LIMIT.as_bytes());
+        let json: Value = serde_json::from_slice(&normalized).unwrap();
+        assert_eq!(json["error"]["code"], "rate_limit_exceeded");
+        assert!(json["error"]["message"].as_str().unwrap().starts_with("Rate limit reached"));
+    }
+
+    #[test]
+    fn non_json_bodies_pass_through_unchanged() {
+        let body = b"<html>502 Bad Gateway</html>";
+        assert_eq!(normalize_response_body(body), body.to_vec());
+    }
+}
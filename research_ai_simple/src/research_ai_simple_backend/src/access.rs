@@ -0,0 +1,186 @@
+// Role-based access control. Roles are ranked, each one including everything
+// the roles below it may do:
+//   Reader   - any caller without a grant: fetch news, manage own subscriptions
+//   Agent    - legacy agent endpoints that spend cycles or write agent data
+//   Operator - run update cycles and curate topics
+//   Admin    - provider settings, budgets and clearing storage
+// Controllers are always Admin, and only controllers grant or revoke roles.
+use candid::{CandidType, Deserialize, Principal};
+use std::fmt;
+
+use crate::storage::{impl_candid_storable, ROLES};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Reader,
+    Agent,
+    Operator,
+    Admin,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RoleGrant {
+    pub principal: Principal,
+    pub role: Role,
+    pub granted_by: Principal,
+    pub granted_at: u64,
+}
+
+impl_candid_storable!(RoleGrant);
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AccessError {
+    Unauthorized { caller: Principal, role: Role, required: Role },
+    // The caller's rate limit for this endpoint is used up until `retry_at`
+    RateLimited { retry_at: u64 },
+    // The anonymous principal stands for every signed-out caller at once,
+    // so it may not own subscriptions
+    AnonymousCaller,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::Unauthorized { caller, role, required } => write!(
+                f,
+                "Caller {} has role {:?} but {:?} is required",
+                caller, role, required
+            ),
+            AccessError::RateLimited { retry_at } => {
+                write!(f, "Rate limit reached, retry at {}", retry_at)
+            }
+            AccessError::AnonymousCaller => {
+                write!(f, "Anonymous callers cannot manage subscriptions; sign in first")
+            }
+        }
+    }
+}
+
+// Lets endpoints with text errors use `?` on a role check
+impl From<AccessError> for String {
+    fn from(error: AccessError) -> Self {
+        error.to_string()
+    }
+}
+
+pub fn role_of(principal: Principal, is_controller: bool) -> Role {
+    if is_controller {
+        return Role::Admin;
+    }
+    ROLES.with(|roles| roles.borrow().get(&principal))
+        .map_or(Role::Reader, |grant| grant.role)
+}
+
+pub fn check(principal: Principal, is_controller: bool, required: Role) -> Result<Role, AccessError> {
+    let role = role_of(principal, is_controller);
+    if role >= required {
+        Ok(role)
+    } else {
+        Err(AccessError::Unauthorized { caller: principal, role, required })
+    }
+}
+
+// Check the current caller, returning its role
+pub fn require(required: Role) -> Result<Role, AccessError> {
+    let caller = ic_cdk::caller();
+    check(caller, ic_cdk::api::is_controller(&caller), required)
+}
+
+// Like `require`, but also turns away the anonymous principal
+pub fn require_signed_in(required: Role) -> Result<Role, AccessError> {
+    if ic_cdk::caller() == Principal::anonymous() {
+        return Err(AccessError::AnonymousCaller);
+    }
+    require(required)
+}
+
+// Replaces any earlier grant. Granting Reader just drops the grant, since
+// every caller is a reader anyway.
+pub fn grant(principal: Principal, role: Role, granted_by: Principal, now: u64) {
+    if role == Role::Reader {
+        revoke(principal);
+        return;
+    }
+    ROLES.with(|roles| {
+        roles.borrow_mut().insert(principal, RoleGrant {
+            principal,
+            role,
+            granted_by,
+            granted_at: now,
+        })
+    });
+}
+
+// Returns false if the principal had no grant
+pub fn revoke(principal: Principal) -> bool {
+    ROLES.with(|roles| roles.borrow_mut().remove(&principal).is_some())
+}
+
+pub fn grants() -> Vec<RoleGrant> {
+    ROLES.with(|roles| roles.borrow().iter().map(|(_, grant)| grant).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id; 29])
+    }
+
+    fn allowed(principal: Principal, is_controller: bool) -> Vec<Role> {
+        [Role::Reader, Role::Agent, Role::Operator, Role::Admin]
+            .into_iter()
+            .filter(|required| check(principal, is_controller, *required).is_ok())
+            .collect()
+    }
+
+    #[test]
+    fn callers_without_a_grant_are_readers() {
+        let caller = principal(1);
+        assert_eq!(role_of(caller, false), Role::Reader);
+        assert_eq!(allowed(caller, false), vec![Role::Reader]);
+        assert_eq!(allowed(Principal::anonymous(), false), vec![Role::Reader]);
+        assert_eq!(
+            check(caller, false, Role::Agent),
+            Err(AccessError::Unauthorized { caller, role: Role::Reader, required: Role::Agent })
+        );
+    }
+
+    #[test]
+    fn agents_reach_agent_endpoints_only() {
+        let caller = principal(2);
+        grant(caller, Role::Agent, principal(0), 0);
+        assert_eq!(allowed(caller, false), vec![Role::Reader, Role::Agent]);
+    }
+
+    #[test]
+    fn operators_include_agent_rights_but_not_admin() {
+        let caller = principal(3);
+        grant(caller, Role::Operator, principal(0), 0);
+        assert_eq!(allowed(caller, false), vec![Role::Reader, Role::Agent, Role::Operator]);
+    }
+
+    #[test]
+    fn admins_and_controllers_may_do_everything() {
+        let caller = principal(4);
+        grant(caller, Role::Admin, principal(0), 0);
+        let everything = vec![Role::Reader, Role::Agent, Role::Operator, Role::Admin];
+        assert_eq!(allowed(caller, false), everything);
+        // Controllers need no grant
+        assert_eq!(allowed(principal(5), true), everything);
+    }
+
+    #[test]
+    fn revoking_falls_back_to_reader() {
+        let caller = principal(6);
+        grant(caller, Role::Operator, principal(0), 0);
+        assert!(revoke(caller));
+        assert_eq!(role_of(caller, false), Role::Reader);
+        assert!(!revoke(caller));
+
+        grant(caller, Role::Agent, principal(0), 0);
+        grant(caller, Role::Reader, principal(0), 0);
+        assert!(grants().iter().all(|grant| grant.principal != caller));
+    }
+}
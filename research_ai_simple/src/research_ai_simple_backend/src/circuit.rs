@@ -0,0 +1,122 @@
+// Per-provider circuit breaker. After enough consecutive rate-limit or
+// server errors from one provider, every outcall to it is refused for a
+// cooldown instead of burning cycles on requests that will fail anyway.
+// Breaker state is heap-only: an upgrade closes every circuit.
+use candid::{CandidType, Deserialize};
+use llm_providers::{LlmError, ProviderKind};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// Consecutive 429/5xx responses that open a provider's circuit
+const FAILURE_THRESHOLD: u32 = 5;
+const COOLDOWN_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_errors: u32,
+    open_until: Option<u64>,
+}
+
+thread_local! {
+    static BREAKERS: RefCell<BTreeMap<ProviderKind, Breaker>> = RefCell::default();
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    // Outcalls are refused until `until`
+    Open { until: u64 },
+    // Cooldown is over; the next error opens the circuit again, a success
+    // closes it
+    HalfOpen,
+}
+
+pub fn state(kind: ProviderKind, now: u64) -> CircuitState {
+    BREAKERS.with(|breakers| match breakers.borrow().get(&kind) {
+        Some(Breaker { open_until: Some(until), .. }) if now < *until => {
+            CircuitState::Open { until: *until }
+        }
+        Some(Breaker { open_until: Some(_), .. }) => CircuitState::HalfOpen,
+        _ => CircuitState::Closed,
+    })
+}
+
+// Refuse the call if the provider's circuit is open
+pub fn check(kind: ProviderKind, now: u64) -> Result<(), LlmError> {
+    match state(kind, now) {
+        CircuitState::Open { until } => Err(LlmError::CircuitOpen {
+            provider: kind.name().to_string(),
+            retry_at: until,
+        }),
+        CircuitState::Closed | CircuitState::HalfOpen => Ok(()),
+    }
+}
+
+// Feed an outcall result into the provider's breaker
+pub fn record(kind: ProviderKind, result: &Result<String, LlmError>, now: u64) {
+    BREAKERS.with(|breakers| {
+        let mut breakers = breakers.borrow_mut();
+        let breaker = breakers.entry(kind).or_default();
+        match result {
+            Ok(_) => *breaker = Breaker::default(),
+            Err(error) if trips_breaker(error) => {
+                breaker.consecutive_errors += 1;
+                if breaker.consecutive_errors >= FAILURE_THRESHOLD {
+                    breaker.open_until = Some(now + COOLDOWN_NANOS);
+                }
+            }
+            Err(_) => {}
+        }
+    });
+}
+
+// Only errors that say the provider itself is struggling count; a bad key or
+// an unknown model is a configuration problem, not an outage.
+fn trips_breaker(error: &LlmError) -> bool {
+    match error {
+        LlmError::RateLimited { .. } => true,
+        LlmError::Api { status, .. } => *status >= 500,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_error() -> Result<String, LlmError> {
+        Err(LlmError::Api { status: 503, message: "overloaded".to_string() })
+    }
+
+    #[test]
+    fn opens_after_threshold_and_half_opens_after_cooldown() {
+        let kind = ProviderKind::OpenAi;
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            record(kind, &server_error(), 0);
+        }
+        assert_eq!(state(kind, 0), CircuitState::Closed);
+
+        record(kind, &server_error(), 0);
+        assert_eq!(state(kind, 1), CircuitState::Open { until: COOLDOWN_NANOS });
+        assert!(matches!(check(kind, 1), Err(LlmError::CircuitOpen { .. })));
+
+        assert_eq!(state(kind, COOLDOWN_NANOS), CircuitState::HalfOpen);
+        record(kind, &server_error(), COOLDOWN_NANOS);
+        assert_eq!(
+            state(kind, COOLDOWN_NANOS + 1),
+            CircuitState::Open { until: 2 * COOLDOWN_NANOS }
+        );
+
+        record(kind, &Ok("news".to_string()), 2 * COOLDOWN_NANOS);
+        assert_eq!(state(kind, 2 * COOLDOWN_NANOS), CircuitState::Closed);
+    }
+
+    #[test]
+    fn configuration_errors_do_not_trip_the_breaker() {
+        let kind = ProviderKind::Anthropic;
+        for _ in 0..FAILURE_THRESHOLD * 2 {
+            record(kind, &Err(LlmError::Unauthorized { message: "bad key".to_string() }), 0);
+        }
+        assert_eq!(state(kind, 0), CircuitState::Closed);
+    }
+}
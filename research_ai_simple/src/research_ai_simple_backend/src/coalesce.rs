@@ -0,0 +1,205 @@
+// Coalescing of concurrent fetches for the same topic. The first caller to
+// ask (the leader) makes the outcall; anyone asking for the topic while it is
+// in flight shares the leader's result, so one outcall and one snapshot serve
+// them all.
+//
+// The leader can't wake its followers: the executor resumes a woken future
+// inside whichever message woke it, and the follower would then reply to the
+// leader's call. Instead each follower yields a round at a time with a cheap
+// management canister call and checks whether the result has landed.
+use ic_cdk::api::management_canister::main::raw_rand;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use crate::storage::NewsSnapshot;
+use crate::NewsError;
+
+type FetchResult = Result<NewsSnapshot, NewsError>;
+
+// Rounds a follower waits before giving up and fetching on its own
+const MAX_WAIT_ROUNDS: u32 = 300;
+
+struct InFlight {
+    flight_id: u64,
+    waiters: u32,
+}
+
+struct Finished {
+    // None when the leader was torn down without a result
+    result: Option<FetchResult>,
+    waiters: u32,
+}
+
+thread_local! {
+    // Running fetches by topic id
+    static IN_FLIGHT: RefCell<BTreeMap<u64, InFlight>> = RefCell::default();
+    // Results by flight id, kept until every follower has read them
+    static FINISHED: RefCell<BTreeMap<u64, Finished>> = RefCell::default();
+    static NEXT_FLIGHT_ID: Cell<u64> = const { Cell::new(1) };
+}
+
+enum Join {
+    Lead(Leader),
+    Follow(Follower),
+}
+
+struct Leader {
+    topic_id: u64,
+    flight_id: u64,
+    finished: bool,
+}
+
+struct Follower {
+    topic_id: u64,
+    flight_id: u64,
+    done: bool,
+}
+
+fn join(topic_id: u64) -> Join {
+    IN_FLIGHT.with(|in_flight| {
+        let mut in_flight = in_flight.borrow_mut();
+        if let Some(flight) = in_flight.get_mut(&topic_id) {
+            flight.waiters += 1;
+            return Join::Follow(Follower { topic_id, flight_id: flight.flight_id, done: false });
+        }
+        let flight_id = NEXT_FLIGHT_ID.with(|next| next.replace(next.get() + 1));
+        in_flight.insert(topic_id, InFlight { flight_id, waiters: 0 });
+        Join::Lead(Leader { topic_id, flight_id, finished: false })
+    })
+}
+
+impl Leader {
+    fn finish(mut self, result: Option<FetchResult>) {
+        self.finished = true;
+        let waiters = IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&self.topic_id))
+            .map_or(0, |flight| flight.waiters);
+        if waiters > 0 {
+            FINISHED.with(|finished| {
+                finished.borrow_mut().insert(self.flight_id, Finished { result, waiters })
+            });
+        }
+    }
+}
+
+// A leader dropped before finishing (its call trapped) releases its
+// followers so they can fetch on their own
+impl Drop for Leader {
+    fn drop(&mut self) {
+        if !self.finished {
+            Leader { finished: true, ..*self }.finish(None);
+        }
+    }
+}
+
+impl Follower {
+    // The leader's outcome once it is known: Some(None) if the leader gave up
+    fn take(&mut self) -> Option<Option<FetchResult>> {
+        let result = FINISHED.with(|finished| {
+            let mut finished = finished.borrow_mut();
+            let entry = finished.get_mut(&self.flight_id)?;
+            let result = entry.result.clone();
+            entry.waiters -= 1;
+            if entry.waiters == 0 {
+                finished.remove(&self.flight_id);
+            }
+            Some(result)
+        })?;
+        self.done = true;
+        Some(result)
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let still_running = IN_FLIGHT.with(|in_flight| {
+            match in_flight.borrow_mut().get_mut(&self.topic_id) {
+                Some(flight) if flight.flight_id == self.flight_id => {
+                    flight.waiters -= 1;
+                    true
+                }
+                _ => false,
+            }
+        });
+        if !still_running {
+            self.take();
+        }
+    }
+}
+
+// Run `fetch` for a topic unless a fetch for it is already in flight, in
+// which case wait for and return that fetch's result instead
+pub async fn run<F, Fut>(topic_id: u64, fetch: F) -> FetchResult
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = FetchResult>,
+{
+    match join(topic_id) {
+        Join::Lead(leader) => {
+            let result = fetch().await;
+            leader.finish(Some(result.clone()));
+            result
+        }
+        Join::Follow(mut follower) => {
+            for _ in 0..MAX_WAIT_ROUNDS {
+                if raw_rand().await.is_err() {
+                    break;
+                }
+                match follower.take() {
+                    Some(Some(result)) => return result,
+                    Some(None) => break,
+                    None => {}
+                }
+            }
+            drop(follower);
+            fetch().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_snapshot;
+
+    fn content(result: Option<Option<FetchResult>>) -> String {
+        result.unwrap().unwrap().unwrap().content
+    }
+
+    #[test]
+    fn followers_share_the_leaders_result() {
+        let Join::Lead(leader) = join(1) else { panic!("first caller should lead") };
+        let Join::Follow(mut first) = join(1) else { panic!("second caller should follow") };
+        let Join::Follow(mut second) = join(1) else { panic!("third caller should follow") };
+        assert!(first.take().is_none());
+
+        leader.finish(Some(Ok(test_snapshot(1, "news"))));
+        assert_eq!(content(first.take()), "news");
+        assert_eq!(content(second.take()), "news");
+        assert!(FINISHED.with(|finished| finished.borrow().is_empty()));
+
+        // The next request starts a fresh flight
+        assert!(matches!(join(1), Join::Lead(_)));
+    }
+
+    #[test]
+    fn dropped_leader_releases_its_followers() {
+        let Join::Lead(leader) = join(2) else { panic!("first caller should lead") };
+        let Join::Follow(mut follower) = join(2) else { panic!("second caller should follow") };
+        drop(leader);
+        assert!(matches!(follower.take(), Some(None)));
+        assert!(IN_FLIGHT.with(|in_flight| !in_flight.borrow().contains_key(&2)));
+    }
+
+    #[test]
+    fn dropped_follower_is_not_waited_for() {
+        let Join::Lead(leader) = join(3) else { panic!("first caller should lead") };
+        let Join::Follow(follower) = join(3) else { panic!("second caller should follow") };
+        drop(follower);
+        leader.finish(Some(Ok(test_snapshot(1, "news"))));
+        assert!(FINISHED.with(|finished| finished.borrow().is_empty()));
+    }
+}
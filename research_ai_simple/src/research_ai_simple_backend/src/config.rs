@@ -0,0 +1,299 @@
+use candid::{CandidType, Deserialize};
+use llm_providers::ProviderConfig;
+
+use crate::retention::RetentionPolicy;
+use crate::scheduler;
+use crate::storage::{impl_candid_storable, CONFIG};
+
+// Canister-wide settings, persisted in stable memory
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct CanisterConfig {
+    // Provider used for every topic without its own override
+    pub provider: ProviderConfig,
+    // Refresh cadence for topics without their own interval
+    pub default_interval_secs: Option<u64>,
+    pub limits: Option<Limits>,
+    // How old a stored snapshot get_news may serve instead of calling the
+    // provider (default 5 minutes; 0 always calls the provider)
+    pub cache_max_age_secs: Option<u64>,
+    // Ask the provider to summarize what changed after each new snapshot
+    // (off by default; it costs a second outcall per fetch)
+    pub delta_summaries: Option<bool>,
+    // Shingle similarity (percent) at which a refresh counts as unchanged and
+    // isn't stored (default 90)
+    pub duplicate_threshold_percent: Option<u32>,
+    // How much snapshot history is kept; topics may override parts of it
+    pub retention: Option<RetentionPolicy>,
+}
+
+impl_candid_storable!(CanisterConfig);
+
+impl CanisterConfig {
+    pub fn cache_max_age_secs(&self) -> u64 {
+        self.cache_max_age_secs.unwrap_or(300)
+    }
+
+    pub fn delta_summaries(&self) -> bool {
+        self.delta_summaries.unwrap_or(false)
+    }
+
+    pub fn duplicate_threshold_percent(&self) -> u32 {
+        self.duplicate_threshold_percent.unwrap_or(90)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Limits {
+    // Topics that can be tracked at once
+    pub max_tracked_topics: u32,
+    // Refreshes started per scheduler tick; the rest wait for the next tick
+    pub max_refreshes_per_tick: u32,
+    // Outcalls in flight at once during an update cycle (default 5)
+    pub max_concurrent_refreshes: Option<u32>,
+    // Cycles the scheduler may spend on outcalls per UTC day, not counting
+    // user or agent calls; unlimited when unset
+    pub daily_cycle_budget: Option<u128>,
+    // Below this balance refreshes slow down (default 1T cycles)
+    pub low_cycles_threshold: Option<u128>,
+    // Below this balance scheduled refreshes stop (default 200B cycles)
+    pub critical_cycles_threshold: Option<u128>,
+    // Per-caller limits on paid outcalls; defaults apply when unset
+    pub rate_limits: Option<RateLimits>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_tracked_topics: 100,
+            max_refreshes_per_tick: 10,
+            max_concurrent_refreshes: None,
+            daily_cycle_budget: None,
+            low_cycles_threshold: None,
+            critical_cycles_threshold: None,
+            rate_limits: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_tracked_topics == 0 {
+            return Err("max_tracked_topics must be greater than zero".to_string());
+        }
+        if self.max_refreshes_per_tick == 0 {
+            return Err("max_refreshes_per_tick must be greater than zero".to_string());
+        }
+        if self.max_concurrent_refreshes == Some(0) {
+            return Err("max_concurrent_refreshes must be greater than zero".to_string());
+        }
+        if self.critical_cycles_threshold() >= self.low_cycles_threshold() {
+            return Err("critical_cycles_threshold must be below low_cycles_threshold".to_string());
+        }
+        if let Some(rate_limits) = &self.rate_limits {
+            rate_limits.validate()?;
+        }
+        Ok(())
+    }
+
+    // These limits with unset optional fields taken from `current`. Both
+    // counts are required, so they always come from here.
+    fn or(&self, current: &Limits) -> Limits {
+        Limits {
+            max_tracked_topics: self.max_tracked_topics,
+            max_refreshes_per_tick: self.max_refreshes_per_tick,
+            max_concurrent_refreshes: self.max_concurrent_refreshes.or(current.max_concurrent_refreshes),
+            daily_cycle_budget: self.daily_cycle_budget.or(current.daily_cycle_budget),
+            low_cycles_threshold: self.low_cycles_threshold.or(current.low_cycles_threshold),
+            critical_cycles_threshold: self.critical_cycles_threshold.or(current.critical_cycles_threshold),
+            rate_limits: match (&self.rate_limits, &current.rate_limits) {
+                (Some(own), Some(current)) => Some(own.or(current)),
+                (own, current) => own.clone().or(current.clone()),
+            },
+        }
+    }
+
+    pub fn low_cycles_threshold(&self) -> u128 {
+        self.low_cycles_threshold.unwrap_or(1_000_000_000_000)
+    }
+
+    pub fn critical_cycles_threshold(&self) -> u128 {
+        self.critical_cycles_threshold.unwrap_or(200_000_000_000)
+    }
+
+    pub fn concurrency(&self) -> u32 {
+        self.max_concurrent_refreshes.unwrap_or(5)
+    }
+
+    pub fn rate_limits(&self) -> RateLimits {
+        self.rate_limits.clone().unwrap_or_default()
+    }
+}
+
+// Token bucket: up to `capacity` calls in a burst, refilled at
+// `refill_per_hour` calls per hour
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_per_hour: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    // Default 10 in a burst, 30 per hour
+    pub get_news: Option<RateLimit>,
+    // Default 5 in a burst, 10 per hour
+    pub agent_query: Option<RateLimit>,
+    // Topics one principal may subscribe to (default 20)
+    pub max_topics_per_principal: Option<u32>,
+    // Every anonymous caller, the public web UI included, shares one bucket
+    // per endpoint, so it gets a larger one (default 50 in a burst, 150 per
+    // hour)
+    pub anonymous: Option<RateLimit>,
+}
+
+impl RateLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, limit) in [
+            ("get_news", self.get_news),
+            ("agent_query", self.agent_query),
+            ("anonymous", self.anonymous),
+        ] {
+            if let Some(limit) = limit {
+                if limit.capacity == 0 || limit.refill_per_hour == 0 {
+                    return Err(format!(
+                        "{} capacity and refill_per_hour must be greater than zero",
+                        name
+                    ));
+                }
+            }
+        }
+        if self.max_topics_per_principal == Some(0) {
+            return Err("max_topics_per_principal must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    fn or(&self, current: &RateLimits) -> RateLimits {
+        RateLimits {
+            get_news: self.get_news.or(current.get_news),
+            agent_query: self.agent_query.or(current.agent_query),
+            max_topics_per_principal: self.max_topics_per_principal.or(current.max_topics_per_principal),
+            anonymous: self.anonymous.or(current.anonymous),
+        }
+    }
+
+    pub fn get_news(&self) -> RateLimit {
+        self.get_news.unwrap_or(RateLimit { capacity: 10, refill_per_hour: 30 })
+    }
+
+    pub fn agent_query(&self) -> RateLimit {
+        self.agent_query.unwrap_or(RateLimit { capacity: 5, refill_per_hour: 10 })
+    }
+
+    pub fn anonymous(&self) -> RateLimit {
+        self.anonymous.unwrap_or(RateLimit { capacity: 50, refill_per_hour: 150 })
+    }
+
+    pub fn max_topics_per_principal(&self) -> u32 {
+        self.max_topics_per_principal.unwrap_or(20)
+    }
+}
+
+// Install/upgrade argument. Unset fields keep the current (or default) value.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct InitArgs {
+    pub update_interval_secs: Option<u64>,
+    pub provider: Option<ProviderConfig>,
+    pub limits: Option<Limits>,
+    pub cache_max_age_secs: Option<u64>,
+    pub retention: Option<RetentionPolicy>,
+}
+
+pub fn get() -> CanisterConfig {
+    CONFIG.with(|config| config.borrow().get().clone())
+}
+
+pub fn limits() -> Limits {
+    get().limits.unwrap_or_default()
+}
+
+// Validate and store whatever settings an install or upgrade was given
+pub fn apply_init_args(args: InitArgs) -> Result<(), String> {
+    if let Some(interval_secs) = args.update_interval_secs {
+        scheduler::validate_interval(interval_secs)?;
+    }
+    if let Some(provider) = &args.provider {
+        provider.validate()?;
+    }
+    // Limits merge into the current ones, so an upgrade doesn't reset a
+    // budget or rate limits set since
+    let limits = args.limits.map(|limits| limits.or(&self::limits()));
+    if let Some(limits) = &limits {
+        limits.validate()?;
+    }
+    if let Some(retention) = &args.retention {
+        retention.validate()?;
+    }
+
+    update(|config| {
+        if args.update_interval_secs.is_some() {
+            config.default_interval_secs = args.update_interval_secs;
+        }
+        if let Some(provider) = args.provider {
+            config.provider = provider;
+        }
+        if limits.is_some() {
+            config.limits = limits;
+        }
+        if args.cache_max_age_secs.is_some() {
+            config.cache_max_age_secs = args.cache_max_age_secs;
+        }
+        if args.retention.is_some() {
+            config.retention = args.retention;
+        }
+    });
+    Ok(())
+}
+
+pub fn update(f: impl FnOnce(&mut CanisterConfig)) {
+    CONFIG.with(|config| {
+        let mut config = config.borrow_mut();
+        let mut value = config.get().clone();
+        f(&mut value);
+        config.set(value).expect("failed to store canister config");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_limits_keep_settings_they_leave_unset() {
+        update(|config| {
+            config.limits = Some(Limits {
+                daily_cycle_budget: Some(5_000),
+                rate_limits: Some(RateLimits { max_topics_per_principal: Some(3), ..Default::default() }),
+                ..Limits::default()
+            });
+        });
+        let args = InitArgs {
+            limits: Some(Limits {
+                max_tracked_topics: 50,
+                rate_limits: Some(RateLimits {
+                    get_news: Some(RateLimit { capacity: 2, refill_per_hour: 4 }),
+                    ..Default::default()
+                }),
+                ..Limits::default()
+            }),
+            ..Default::default()
+        };
+        apply_init_args(args).unwrap();
+
+        let limits = limits();
+        assert_eq!(limits.max_tracked_topics, 50);
+        assert_eq!(limits.daily_cycle_budget, Some(5_000));
+        assert_eq!(limits.rate_limits().max_topics_per_principal, Some(3));
+        assert_eq!(limits.rate_limits().get_news().capacity, 2);
+    }
+}
@@ -0,0 +1,174 @@
+// Cycle accounting for HTTP outcalls. Each outcall is priced from its
+// payload size and response cap instead of a flat fee, and what it actually
+// cost is added to running totals per topic, per caller and per UTC day.
+// Spend by the canister's own timers is also totalled per day on its own,
+// since that is what the daily budget caps.
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::cmp::Reverse;
+
+use crate::config;
+use crate::storage::{Memory, COST_BY_CALLER, COST_BY_DAY, COST_BY_TOPIC, SCHEDULED_COST_BY_DAY};
+use crate::topics;
+
+// HTTP outcall pricing on a 13-node application subnet
+const SUBNET_SIZE: u128 = 13;
+const BASE_FEE_PER_NODE: u128 = 3_000_000 + 60_000 * SUBNET_SIZE;
+const REQUEST_BYTE_FEE_PER_NODE: u128 = 400;
+const RESPONSE_BYTE_FEE_PER_NODE: u128 = 800;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Days of per-day totals included in the cost report
+const REPORT_DAYS: u64 = 30;
+
+// Who an outcall is charged to
+#[derive(Clone, Copy, Debug)]
+pub struct Attribution {
+    pub topic_id: Option<u64>,
+    pub caller: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TopicCost {
+    pub topic_id: u64,
+    pub topic: String,
+    pub cycles: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CallerCost {
+    pub caller: Principal,
+    pub cycles: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DayCost {
+    // Days since the Unix epoch (UTC)
+    pub day: u64,
+    pub cycles: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CostReport {
+    pub today: u64,
+    pub spent_today: u128,
+    // The part of spent_today the budget applies to
+    pub scheduled_today: u128,
+    pub daily_budget: Option<u128>,
+    pub budget_exhausted: bool,
+    pub total_spent: u128,
+    pub by_topic: Vec<TopicCost>,
+    pub by_caller: Vec<CallerCost>,
+    // The last REPORT_DAYS days with any spend, newest first
+    pub by_day: Vec<DayCost>,
+}
+
+// Cycles to attach to an outcall sending `request_bytes` and accepting up
+// to `max_response_bytes` back. Anything unused is refunded.
+pub fn outcall_cost(request_bytes: u64, max_response_bytes: u64) -> u128 {
+    SUBNET_SIZE * (
+        BASE_FEE_PER_NODE
+            + REQUEST_BYTE_FEE_PER_NODE * request_bytes as u128
+            + RESPONSE_BYTE_FEE_PER_NODE * max_response_bytes as u128
+    )
+}
+
+pub fn day_of(timestamp: u64) -> u64 {
+    timestamp / NANOS_PER_DAY
+}
+
+pub fn charge(attribution: Attribution, cycles: u128, now: u64) {
+    if cycles == 0 {
+        return;
+    }
+    if let Some(topic_id) = attribution.topic_id {
+        COST_BY_TOPIC.with(|costs| add(&mut costs.borrow_mut(), topic_id, cycles));
+    }
+    COST_BY_CALLER.with(|costs| add(&mut costs.borrow_mut(), attribution.caller, cycles));
+    COST_BY_DAY.with(|costs| add(&mut costs.borrow_mut(), day_of(now), cycles));
+    if attribution.caller == ic_cdk::id() {
+        SCHEDULED_COST_BY_DAY.with(|costs| add(&mut costs.borrow_mut(), day_of(now), cycles));
+    }
+}
+
+fn add<K: Storable + Ord + Clone>(totals: &mut StableBTreeMap<K, u128, Memory>, key: K, cycles: u128) {
+    let total = totals.get(&key).unwrap_or(0).saturating_add(cycles);
+    totals.insert(key, total);
+}
+
+pub fn spent_on(day: u64) -> u128 {
+    COST_BY_DAY.with(|costs| costs.borrow().get(&day).unwrap_or(0))
+}
+
+pub fn scheduled_spent_on(day: u64) -> u128 {
+    SCHEDULED_COST_BY_DAY.with(|costs| costs.borrow().get(&day).unwrap_or(0))
+}
+
+// Whether today's scheduled spend has reached the configured daily budget
+pub fn budget_exhausted(now: u64) -> bool {
+    match config::limits().daily_cycle_budget {
+        Some(budget) => scheduled_spent_on(day_of(now)) >= budget,
+        None => false,
+    }
+}
+
+pub fn report(now: u64) -> CostReport {
+    let today = day_of(now);
+    let daily_budget = config::limits().daily_cycle_budget;
+    let spent_today = spent_on(today);
+    let scheduled_today = scheduled_spent_on(today);
+
+    let mut by_topic: Vec<TopicCost> = COST_BY_TOPIC.with(|costs| {
+        costs.borrow().iter().map(|(topic_id, cycles)| TopicCost {
+            topic_id,
+            // Merged-away topics keep their spend under the old id
+            topic: topics::get_topic(topic_id)
+                .map(|record| record.display_name)
+                .unwrap_or_default(),
+            cycles,
+        }).collect()
+    });
+    by_topic.sort_by_key(|cost| Reverse(cost.cycles));
+
+    let mut by_caller: Vec<CallerCost> = COST_BY_CALLER.with(|costs| {
+        costs.borrow().iter().map(|(caller, cycles)| CallerCost { caller, cycles }).collect()
+    });
+    by_caller.sort_by_key(|cost| Reverse(cost.cycles));
+
+    let (total_spent, by_day) = COST_BY_DAY.with(|costs| {
+        let costs = costs.borrow();
+        let total = costs.iter().map(|(_, cycles)| cycles).sum();
+        let recent = costs.range(today.saturating_sub(REPORT_DAYS - 1)..)
+            .rev()
+            .map(|(day, cycles)| DayCost { day, cycles })
+            .collect();
+        (total, recent)
+    });
+
+    CostReport {
+        today,
+        spent_today,
+        scheduled_today,
+        daily_budget,
+        budget_exhausted: daily_budget.is_some_and(|budget| scheduled_today >= budget),
+        total_spent,
+        by_topic,
+        by_caller,
+        by_day,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcall_cost_grows_with_payload_and_response_cap() {
+        let base = outcall_cost(0, 0);
+        assert_eq!(base, 49_140_000);
+        assert_eq!(outcall_cost(1, 0) - base, 5_200);
+        assert_eq!(outcall_cost(0, 1) - base, 10_400);
+        // A typical news request stays far below the old flat 25B cycles
+        assert!(outcall_cost(1_024, 4_096) < 100_000_000);
+    }
+}
@@ -0,0 +1,43 @@
+use candid::{CandidType, Deserialize};
+use llm_providers::ProviderKind;
+
+use crate::storage::{impl_candid_storable, CREDENTIALS};
+
+// Secret used to authenticate outcalls to an LLM provider. Deliberately not
+// `Debug` and not part of the Candid interface, so it can't leak through
+// logs or query responses.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ProviderCredentials {
+    pub api_key: String,
+    pub updated_at: u64,
+}
+
+impl_candid_storable!(ProviderCredentials);
+
+pub fn api_key(kind: ProviderKind) -> Option<String> {
+    CREDENTIALS.with(|credentials| {
+        credentials.borrow().get(&kind.name().to_string()).map(|c| c.api_key)
+    })
+}
+
+// Providers that have a key, with when it was last set
+pub fn configured_providers() -> Vec<(String, u64)> {
+    CREDENTIALS.with(|credentials| {
+        credentials.borrow().iter().map(|(name, c)| (name, c.updated_at)).collect()
+    })
+}
+
+pub fn set_api_key(kind: ProviderKind, api_key: String) -> Result<(), String> {
+    let api_key = api_key.trim().to_string();
+    if api_key.is_empty() {
+        return Err("API key must not be empty".to_string());
+    }
+
+    CREDENTIALS.with(|credentials| {
+        credentials.borrow_mut().insert(kind.name().to_string(), ProviderCredentials {
+            api_key,
+            updated_at: ic_cdk::api::time(),
+        });
+    });
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Update cycle runner. A cycle refreshes a batch of topics with at most
+// `max_concurrent_refreshes` outcalls in flight, never overlaps another
+// cycle, and leaves a report of how each topic went.
+use candid::{CandidType, Deserialize, Principal};
+use futures::stream::{self, StreamExt};
+use std::cell::Cell;
+
+use crate::config;
+use crate::storage::{impl_candid_storable, SnapshotStatus, CYCLE_REPORTS};
+use crate::topics;
+use crate::NewsError;
+
+// Completed reports kept in stable memory; older ones are dropped
+const MAX_STORED_REPORTS: u64 = 50;
+
+thread_local! {
+    static CYCLE_RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CycleTrigger {
+    Scheduled,
+    Manual,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TopicOutcome {
+    pub topic_id: u64,
+    pub topic: String,
+    pub status: SnapshotStatus,
+    pub started_at: u64,
+    pub duration_ns: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CycleReport {
+    pub cycle_id: u64,
+    pub trigger: CycleTrigger,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub concurrency: u32,
+    pub succeeded: u32,
+    pub failed: u32,
+    pub outcomes: Vec<TopicOutcome>,
+}
+
+impl_candid_storable!(CycleReport);
+
+// Held for the lifetime of a cycle. Dropping it, including when the cycle's
+// future is torn down after a trap, lets the next cycle start.
+struct CycleGuard;
+
+impl CycleGuard {
+    fn acquire() -> Option<Self> {
+        CYCLE_RUNNING.with(|running| {
+            if running.replace(true) {
+                None
+            } else {
+                Some(CycleGuard)
+            }
+        })
+    }
+}
+
+impl Drop for CycleGuard {
+    fn drop(&mut self) {
+        CYCLE_RUNNING.with(|running| running.set(false));
+    }
+}
+
+pub fn is_running() -> bool {
+    CYCLE_RUNNING.with(|running| running.get())
+}
+
+// Refresh `topic_ids`, charging outcalls to `caller`, and store the report.
+// Fails without doing anything if another cycle is still running.
+pub async fn run(
+    trigger: CycleTrigger,
+    caller: Principal,
+    topic_ids: Vec<u64>,
+) -> Result<CycleReport, String> {
+    let Some(_guard) = CycleGuard::acquire() else {
+        return Err("An update cycle is already running".to_string());
+    };
+
+    let concurrency = config::limits().concurrency();
+    let started_at = ic_cdk::api::time();
+    let outcomes: Vec<TopicOutcome> = stream::iter(topic_ids.into_iter().filter_map(topics::get_topic))
+        .map(|record| async move {
+            let started_at = ic_cdk::api::time();
+            let topic = record.display_name.clone();
+            let status = match crate::fetch_news(&record, &topic, caller).await {
+                Ok(_) => SnapshotStatus::Ok,
+                Err(NewsError::FetchFailed { code, message, .. }) => {
+                    SnapshotStatus::Failed { code, message }
+                }
+                Err(NewsError::ProviderUnavailable { provider, retry_at }) => {
+                    SnapshotStatus::Failed {
+                        code: "circuit_open".to_string(),
+                        message: format!("{} paused until {}", provider, retry_at),
+                    }
+                }
+                Err(error) => SnapshotStatus::Failed {
+                    code: "internal".to_string(),
+                    message: format!("{:?}", error),
+                },
+            };
+            TopicOutcome {
+                topic_id: record.id,
+                topic,
+                status,
+                started_at,
+                duration_ns: ic_cdk::api::time().saturating_sub(started_at),
+            }
+        })
+        .buffer_unordered(concurrency as usize)
+        .collect()
+        .await;
+
+    let failed = outcomes.iter().filter(|o| o.status != SnapshotStatus::Ok).count() as u32;
+    let report = CycleReport {
+        cycle_id: next_cycle_id(),
+        trigger,
+        started_at,
+        finished_at: ic_cdk::api::time(),
+        concurrency,
+        succeeded: outcomes.len() as u32 - failed,
+        failed,
+        outcomes,
+    };
+    store_report(report.clone());
+    Ok(report)
+}
+
+// A stored report by id, or the most recent one
+pub fn report(cycle_id: Option<u64>) -> Option<CycleReport> {
+    CYCLE_REPORTS.with(|reports| {
+        let reports = reports.borrow();
+        match cycle_id {
+            Some(id) => reports.get(&id),
+            None => reports.last_key_value().map(|(_, report)| report),
+        }
+    })
+}
+
+fn next_cycle_id() -> u64 {
+    CYCLE_REPORTS.with(|reports| {
+        reports.borrow().last_key_value().map(|(id, _)| id + 1).unwrap_or(1)
+    })
+}
+
+fn store_report(report: CycleReport) {
+    CYCLE_REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        reports.insert(report.cycle_id, report);
+        while reports.len() > MAX_STORED_REPORTS {
+            reports.pop_first();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_blocks_overlapping_cycles_until_dropped() {
+        let guard = CycleGuard::acquire().expect("first cycle should start");
+        assert!(is_running());
+        assert!(CycleGuard::acquire().is_none());
+
+        drop(guard);
+        assert!(!is_running());
+        assert!(CycleGuard::acquire().is_some());
+    }
+}
@@ -0,0 +1,109 @@
+// Duplicate detection for refreshed news. A refresh whose text hashes the
+// same as the last stored snapshot, or whose word shingles overlap it by at
+// least the configured Jaccard similarity, is treated as unchanged and not
+// stored again.
+use std::collections::BTreeSet;
+
+use crate::storage::NewsSnapshot;
+
+// Words per shingle
+const SHINGLE_SIZE: usize = 3;
+
+// FNV-1a, so hashes stay stable across compiler and library versions
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Lowercased words with punctuation stripped
+fn words(content: &str) -> Vec<String> {
+    content.split_whitespace()
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// Hash of the content with case, punctuation and whitespace ignored
+pub fn content_hash(content: &str) -> u64 {
+    fnv1a(words(content).join(" ").as_bytes())
+}
+
+fn shingles(content: &str) -> BTreeSet<u64> {
+    let words = words(content);
+    if words.len() < SHINGLE_SIZE {
+        return words.iter().map(|word| fnv1a(word.as_bytes())).collect();
+    }
+    words.windows(SHINGLE_SIZE)
+        .map(|shingle| fnv1a(shingle.join(" ").as_bytes()))
+        .collect()
+}
+
+// Jaccard similarity of the two texts' shingle sets, as a percentage
+pub fn similarity_percent(a: &str, b: &str) -> u32 {
+    let (a, b) = (shingles(a), shingles(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 100;
+    }
+    let intersection = a.intersection(&b).count();
+    (intersection * 100 / union) as u32
+}
+
+pub fn is_duplicate(previous: &str, content: &str, threshold_percent: u32) -> bool {
+    content_hash(previous) == content_hash(content)
+        || similarity_percent(previous, content) >= threshold_percent
+}
+
+// What an unchanged refresh replies with: the stored news stamped with the
+// time of the check, so callers can see a live check just happened. There
+// is no change to summarize.
+pub fn unchanged(previous: &NewsSnapshot, checked_at: u64) -> NewsSnapshot {
+    NewsSnapshot {
+        timestamp: checked_at,
+        delta_summary: None,
+        ..previous.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_snapshot;
+
+    const NEWS: &str = "The central bank held rates at 5% on Tuesday. Markets rallied on \
+        the news, with tech stocks leading gains. Analysts expect a cut in the spring.";
+
+    #[test]
+    fn formatting_changes_hash_the_same() {
+        let reformatted = "the central bank held rates at 5% on Tuesday\n\nMarkets rallied on the \
+            news -- with tech stocks leading gains. Analysts expect a cut in the spring!";
+        assert_eq!(content_hash(NEWS), content_hash(reformatted));
+        assert!(is_duplicate(NEWS, reformatted, 100));
+    }
+
+    #[test]
+    fn small_edits_stay_above_the_threshold_and_new_stories_do_not() {
+        let edited = NEWS.replace("Tuesday", "Wednesday");
+        let similarity = similarity_percent(NEWS, &edited);
+        assert!((75..100).contains(&similarity), "similarity {}", similarity);
+        assert!(is_duplicate(NEWS, &edited, 75));
+        assert!(!is_duplicate(NEWS, &edited, 100));
+
+        let new_story = "A new chief executive was named after the merger closed. Shares fell 3%.";
+        assert!(similarity_percent(NEWS, new_story) < 10);
+        assert!(!is_duplicate(NEWS, new_story, 90));
+    }
+
+    #[test]
+    fn unchanged_refresh_reports_the_check_time() {
+        let previous = NewsSnapshot { delta_summary: Some("Rates held".to_string()), ..test_snapshot(10, NEWS) };
+        let refreshed = NEWS.replace("Tuesday", "Wednesday");
+        assert!(is_duplicate(&previous.content, &refreshed, 75));
+
+        let reply = unchanged(&previous, 25);
+        assert_eq!(reply.timestamp, 25);
+        assert_eq!(reply.content, previous.content);
+        assert_eq!(reply.delta_summary, None);
+    }
+}
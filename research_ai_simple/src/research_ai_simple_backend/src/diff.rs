@@ -0,0 +1,121 @@
+// Sentence-level diffs between consecutive news snapshots. Content is split
+// into lines and then sentences, compared with whitespace and case folded,
+// and matched up with a longest-common-subsequence pass so reordered
+// paragraphs show up as removed and re-added rather than as noise.
+use candid::{CandidType, Deserialize};
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnapshotDiff {
+    pub from_timestamp: u64,
+    pub to_timestamp: u64,
+    // Sentences in the newer snapshot only, in its order
+    pub added: Vec<String>,
+    // Sentences in the older snapshot only, in its order
+    pub removed: Vec<String>,
+    pub unchanged: u32,
+    // LLM summary of what's new, when summaries were enabled at fetch time
+    pub delta_summary: Option<String>,
+}
+
+pub struct SentenceDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: u32,
+}
+
+pub fn sentences(content: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    for line in content.lines() {
+        let mut current = String::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            current.push(c);
+            let at_boundary = matches!(c, '.' | '!' | '?')
+                && chars.peek().is_none_or(|next| next.is_whitespace());
+            if at_boundary {
+                push_sentence(&mut sentences, &current);
+                current.clear();
+            }
+        }
+        push_sentence(&mut sentences, &current);
+    }
+    sentences
+}
+
+fn push_sentence(sentences: &mut Vec<String>, text: &str) {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Bare list markers and separators aren't sentences
+    if text.chars().any(char::is_alphanumeric) {
+        sentences.push(text);
+    }
+}
+
+fn comparable(sentence: &str) -> String {
+    sentence.to_lowercase()
+}
+
+pub fn diff(old: &str, new: &str) -> SentenceDiff {
+    let old = sentences(old);
+    let new = sentences(new);
+    let old_keys: Vec<String> = old.iter().map(|s| comparable(s)).collect();
+    let new_keys: Vec<String> = new.iter().map(|s| comparable(s)).collect();
+
+    // lcs[i][j]: common subsequence length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old_keys[i] == new_keys[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = SentenceDiff { added: Vec::new(), removed: Vec::new(), unchanged: 0 };
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old_keys[i] == new_keys[j] {
+            result.unchanged += 1;
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.removed.push(old[i].clone());
+            i += 1;
+        } else {
+            result.added.push(new[j].clone());
+            j += 1;
+        }
+    }
+    result.removed.extend(old[i..].iter().cloned());
+    result.added.extend(new[j..].iter().cloned());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines_and_sentences() {
+        let content = "## Headlines\n- Rates held at 5%. Markets rallied!\n\n---\nVersion 2.1 shipped";
+        assert_eq!(
+            sentences(content),
+            vec!["## Headlines", "- Rates held at 5%.", "Markets rallied!", "Version 2.1 shipped"]
+        );
+    }
+
+    #[test]
+    fn reports_only_new_and_dropped_sentences() {
+        let old = "Rates held at 5%. Markets rallied.\nOil fell.";
+        let new = "Rates held at 5%.  markets rallied.\nOil rose. A new CEO was named.";
+        let diff = diff(old, new);
+        assert_eq!(diff.unchanged, 2);
+        assert_eq!(diff.removed, vec!["Oil fell."]);
+        assert_eq!(diff.added, vec!["Oil rose.", "A new CEO was named."]);
+
+        let same = super::diff(old, old);
+        assert!(same.added.is_empty() && same.removed.is_empty());
+        assert_eq!(same.unchanged, 3);
+    }
+}
@@ -0,0 +1,333 @@
+// Daily and weekly digests. Once a UTC day or week (starting Monday) is
+// over, the scheduler feeds a sample of each tracked topic's successful
+// snapshots from that period to the topic's provider and stores the rollup
+// as a digest, separate from the snapshot history.
+use candid::{CandidType, Deserialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::costs::Attribution;
+use crate::llm;
+use crate::storage::{self, impl_candid_storable, DigestKey, DIGESTS};
+use crate::topics;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const NANOS_PER_WEEK: u64 = 7 * NANOS_PER_DAY;
+// The Unix epoch was a Thursday, three days after the Monday weeks start on
+const WEEK_OFFSET: u64 = 3 * NANOS_PER_DAY;
+
+// Digests generated per scheduler tick; the rest wait for later ticks
+const DIGESTS_PER_TICK: usize = 2;
+// How far back missed periods are caught up on
+const BACKFILL_DAYS: u64 = 7;
+const BACKFILL_WEEKS: u64 = 4;
+// A digest that failed is attempted again after this long
+const RETRY_AFTER_NANOS: u64 = 60 * 60 * 1_000_000_000;
+// Snapshots read for one digest, and how many of those reach the prompt
+const MAX_PERIOD_SNAPSHOTS: usize = 2_000;
+const MAX_DIGEST_INPUTS: usize = 8;
+// Characters of each sampled snapshot included in the prompt
+const MAX_INPUT_CHARS: usize = 2_000;
+// Digests returned by one get_digests call
+pub const MAX_DIGESTS: usize = 100;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl DigestPeriod {
+    pub const ALL: [DigestPeriod; 2] = [DigestPeriod::Daily, DigestPeriod::Weekly];
+
+    fn key(self) -> u8 {
+        match self {
+            DigestPeriod::Daily => 0,
+            DigestPeriod::Weekly => 1,
+        }
+    }
+
+    fn length(self) -> u64 {
+        match self {
+            DigestPeriod::Daily => NANOS_PER_DAY,
+            DigestPeriod::Weekly => NANOS_PER_WEEK,
+        }
+    }
+
+    // Most recent periods a topic may still get a digest for
+    fn backfill(self) -> u64 {
+        match self {
+            DigestPeriod::Daily => BACKFILL_DAYS,
+            DigestPeriod::Weekly => BACKFILL_WEEKS,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            DigestPeriod::Daily => "day",
+            DigestPeriod::Weekly => "week",
+        }
+    }
+
+    // Start of the UTC day or week containing `timestamp`
+    pub fn start_of(self, timestamp: u64) -> u64 {
+        match self {
+            DigestPeriod::Daily => timestamp / NANOS_PER_DAY * NANOS_PER_DAY,
+            DigestPeriod::Weekly => {
+                ((timestamp + WEEK_OFFSET) / NANOS_PER_WEEK * NANOS_PER_WEEK).saturating_sub(WEEK_OFFSET)
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Digest {
+    pub topic_id: u64,
+    pub topic: String,
+    pub period: DigestPeriod,
+    pub period_start: u64,
+    // Exclusive
+    pub period_end: u64,
+    pub summary: String,
+    // Successful snapshots stored in the period
+    pub snapshot_count: u32,
+    pub created_at: u64,
+}
+
+impl_candid_storable!(Digest);
+
+// Bounds on period_start; open ends when unset
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct TimeRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+thread_local! {
+    // When each digest was last attempted, so overlapping ticks don't both
+    // generate it and a failing one isn't retried every tick
+    static ATTEMPTS: RefCell<BTreeMap<DigestKey, u64>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn key(topic_id: u64, period: DigestPeriod, period_start: u64) -> DigestKey {
+    (topic_id, period.key(), period_start)
+}
+
+pub fn insert(digest: Digest) {
+    let key = key(digest.topic_id, digest.period, digest.period_start);
+    DIGESTS.with(|digests| digests.borrow_mut().insert(key, digest));
+}
+
+// A topic's digests for one period length, newest first
+pub fn range(topic_id: u64, period: DigestPeriod, range: &TimeRange) -> Vec<Digest> {
+    let since = range.since.unwrap_or(0);
+    let until = range.until.unwrap_or(u64::MAX);
+    if since > until {
+        return Vec::new();
+    }
+    DIGESTS.with(|digests| {
+        digests.borrow()
+            .range(key(topic_id, period, since)..=key(topic_id, period, until))
+            .rev()
+            .take(MAX_DIGESTS)
+            .map(|(_, digest)| digest)
+            .collect()
+    })
+}
+
+// Claim the digests due now: every complete day and week in the backfill
+// window that had successful snapshots and has no digest yet, newest first.
+// Periods missed while digests were paused (e.g. the budget was exhausted)
+// are caught up on over later ticks this way.
+pub fn take_due(topic_ids: &[u64], now: u64) -> Vec<(u64, DigestPeriod, u64)> {
+    // Attempts older than the retry delay no longer hold anything back
+    ATTEMPTS.with(|attempts| attempts.borrow_mut().retain(|_, at| now < *at + RETRY_AFTER_NANOS));
+    let mut due = Vec::new();
+    for &topic_id in topic_ids {
+        for period in DigestPeriod::ALL {
+            let Some(latest) = period.start_of(now).checked_sub(period.length()) else {
+                continue;
+            };
+            let oldest = latest.saturating_sub((period.backfill() - 1) * period.length());
+            let mut period_start = latest;
+            while period_start >= oldest {
+                if due.len() == DIGESTS_PER_TICK {
+                    return due;
+                }
+                let key = key(topic_id, period, period_start);
+                let claimable = !ATTEMPTS.with(|attempts| attempts.borrow().contains_key(&key))
+                    && !DIGESTS.with(|digests| digests.borrow().contains_key(&key))
+                    && storage::count_snapshots(topic_id, period_start, period_start + period.length() - 1, false) > 0;
+                if claimable {
+                    ATTEMPTS.with(|attempts| attempts.borrow_mut().insert(key, now));
+                    due.push((topic_id, period, period_start));
+                }
+                match period_start.checked_sub(period.length()) {
+                    Some(previous) => period_start = previous,
+                    None => break,
+                }
+            }
+        }
+    }
+    due
+}
+
+// Up to `max` items spread evenly from first to last
+fn sample<T: Clone>(items: &[T], max: usize) -> Vec<T> {
+    if items.len() <= max || max < 2 {
+        return items.iter().take(max).cloned().collect();
+    }
+    (0..max).map(|i| items[i * (items.len() - 1) / (max - 1)].clone()).collect()
+}
+
+// Summarize one period of a topic and store the digest
+pub async fn generate(
+    topic_id: u64,
+    period: DigestPeriod,
+    period_start: u64,
+    attribution: Attribution,
+) -> Result<Digest, String> {
+    let record = topics::get_topic(topic_id)
+        .ok_or_else(|| format!("Unknown topic id: {}", topic_id))?;
+    let period_end = period_start + period.length();
+    let (snapshots, _) = storage::snapshot_range(
+        topic_id, period_start, period_end - 1, false, MAX_PERIOD_SNAPSHOTS, false,
+    );
+    if snapshots.is_empty() {
+        return Err(format!("No news stored for '{}' in that {}", record.display_name, period.label()));
+    }
+
+    let updates: Vec<(u64, String)> = sample(&snapshots, MAX_DIGEST_INPUTS)
+        .into_iter()
+        .map(|snapshot| (snapshot.timestamp, snapshot.content.chars().take(MAX_INPUT_CHARS).collect()))
+        .collect();
+    let prompt = llm::digest_prompt(&record.display_name, period.label(), &updates);
+    let provider = llm::provider_config_for(Some(&record));
+    let summary = llm::complete(&provider, &prompt, attribution)
+        .await
+        .map_err(|error| error.to_string())?;
+
+    let digest = Digest {
+        topic_id,
+        topic: record.display_name,
+        period,
+        period_start,
+        period_end,
+        summary,
+        snapshot_count: snapshots.len() as u32,
+        created_at: ic_cdk::api::time(),
+    };
+    insert(digest.clone());
+    Ok(digest)
+}
+
+// Hand `source`'s digests over to `target`, keeping the target's own where
+// both have one for the same period
+pub fn move_digests(source: u64, target: u64) {
+    DIGESTS.with(|digests| {
+        let mut digests = digests.borrow_mut();
+        let moved: Vec<_> = digests
+            .range((source, 0, 0)..=(source, u8::MAX, u64::MAX))
+            .collect();
+        for ((_, period, period_start), mut digest) in moved {
+            digests.remove(&(source, period, period_start));
+            if !digests.contains_key(&(target, period, period_start)) {
+                digest.topic_id = target;
+                digests.insert((target, period, period_start), digest);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{insert_snapshot, test_snapshot};
+
+    // 2024-01-03 12:00 UTC, a Wednesday
+    const WEDNESDAY_NOON: u64 = 1_704_283_200 * 1_000_000_000;
+    // 2024-01-01 00:00 UTC, the Monday before
+    const MONDAY: u64 = 1_704_067_200 * 1_000_000_000;
+
+    #[test]
+    fn periods_start_at_utc_midnight_and_on_mondays() {
+        assert_eq!(DigestPeriod::Daily.start_of(WEDNESDAY_NOON), MONDAY + 2 * NANOS_PER_DAY);
+        assert_eq!(DigestPeriod::Weekly.start_of(WEDNESDAY_NOON), MONDAY);
+        assert_eq!(DigestPeriod::Weekly.start_of(MONDAY), MONDAY);
+        assert_eq!(DigestPeriod::Weekly.start_of(MONDAY - 1), MONDAY - NANOS_PER_WEEK);
+    }
+
+    #[test]
+    fn sample_spreads_across_the_period() {
+        let items: Vec<u32> = (0..20).collect();
+        assert_eq!(sample(&items, 5), vec![0, 4, 9, 14, 19]);
+        assert_eq!(sample(&items[..3], 5), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn only_finished_periods_with_news_are_due_once() {
+        let yesterday = MONDAY + NANOS_PER_DAY;
+        insert_snapshot(1, test_snapshot(yesterday + 5, "news"));
+
+        // Last week had no news for topic 1, and topic 2 has none at all
+        let due = take_due(&[1, 2], WEDNESDAY_NOON);
+        assert_eq!(due, vec![(1, DigestPeriod::Daily, yesterday)]);
+        // Claimed digests aren't handed out again until the retry delay
+        assert!(take_due(&[1, 2], WEDNESDAY_NOON + 1).is_empty());
+        assert_eq!(take_due(&[1], WEDNESDAY_NOON + RETRY_AFTER_NANOS).len(), 1);
+    }
+
+    #[test]
+    fn missed_periods_since_the_last_digest_are_backfilled() {
+        let saturday = MONDAY - 2 * NANOS_PER_DAY;
+        let tuesday = MONDAY + NANOS_PER_DAY;
+        for timestamp in [saturday, MONDAY, tuesday] {
+            insert_snapshot(3, test_snapshot(timestamp + 5, "news"));
+        }
+        insert(digest(3, DigestPeriod::Daily, saturday));
+
+        // Both days since Saturday's digest, newest first
+        let due = take_due(&[3], WEDNESDAY_NOON);
+        assert_eq!(due, vec![(3, DigestPeriod::Daily, tuesday), (3, DigestPeriod::Daily, MONDAY)]);
+        // Saturday already has its digest; last week's is next
+        let due = take_due(&[3], WEDNESDAY_NOON + 1);
+        assert_eq!(due, vec![(3, DigestPeriod::Weekly, MONDAY - NANOS_PER_WEEK)]);
+    }
+
+    #[test]
+    fn gaps_longer_than_one_tick_are_filled_over_several_ticks() {
+        // News on each of the six days up to yesterday, none digested yet
+        let yesterday = MONDAY + NANOS_PER_DAY;
+        let days: Vec<u64> = (0..6).rev().map(|back| yesterday - back * NANOS_PER_DAY).collect();
+        for &day in &days {
+            insert_snapshot(4, test_snapshot(day + 5, "news"));
+        }
+
+        let mut generated = Vec::new();
+        for tick in 0..10 {
+            let due = take_due(&[4], WEDNESDAY_NOON + tick);
+            assert!(due.len() <= DIGESTS_PER_TICK);
+            for (topic_id, period, period_start) in due {
+                insert(digest(topic_id, period, period_start));
+                if period == DigestPeriod::Daily {
+                    generated.push(period_start);
+                }
+            }
+        }
+        generated.sort_unstable();
+        assert_eq!(generated, days);
+    }
+
+    fn digest(topic_id: u64, period: DigestPeriod, period_start: u64) -> Digest {
+        Digest {
+            topic_id,
+            topic: "topic".to_string(),
+            period,
+            period_start,
+            period_end: period_start + period.length(),
+            summary: "summary".to_string(),
+            snapshot_count: 1,
+            created_at: period_start + period.length(),
+        }
+    }
+}
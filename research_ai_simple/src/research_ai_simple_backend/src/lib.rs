@@ -1,30 +1,61 @@
-use ic_cdk::{query, update, export_candid, init};
-use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
-};
-use candid::{CandidType, Deserialize};
-use std::collections::{HashMap, HashSet};
-use std::cell::RefCell;
-use ic_cdk_timers::set_timer_interval;
-use std::time::Duration;
-
-// Storage structures
-thread_local! {
-    // Main storage for all data
-    static STORAGE: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
-    
-    // Track active news topics for periodic updates
-    static TRACKED_TOPICS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
-    
-    // Store last update times for each topic
-    static LAST_UPDATE: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
-}
+use ic_cdk::{query, update, export_candid, init, post_upgrade};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use candid::{CandidType, Deserialize, Principal};
+use llm_providers::{LlmError, ProviderConfig, ProviderKind};
+
+mod access;
+mod circuit;
+mod coalesce;
+mod config;
+mod costs;
+mod credentials;
+mod cycle;
+mod dedupe;
+mod digests;
+mod diff;
+mod llm;
+mod migrations;
+mod quota;
+mod retention;
+mod scheduler;
+mod search;
+mod storage;
+mod subscriptions;
+mod topics;
+mod watchdog;
+
+use access::{AccessError, Role, RoleGrant};
+use config::InitArgs;
+use circuit::CircuitState;
+use config::RateLimits;
+use costs::{Attribution, CostReport};
+use cycle::{CycleReport, CycleTrigger};
+use diff::SnapshotDiff;
+use digests::{Digest, DigestPeriod, TimeRange};
+use quota::{Action, QuotaStatus};
+use retention::{RetentionPage, RetentionPolicy};
+use scheduler::SchedulerStatus;
+use search::{SearchFilters, SearchPage, SearchResults};
+use storage::{NewsSnapshot, SnapshotStatus, SNAPSHOTS, STORAGE, TRACKED_TOPICS};
+use topics::{TopicFailure, TopicRecord};
+use watchdog::{AlertEvent, CyclesLevel};
 
 // Request/Response types
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct NewsQuery {
     pub topic: String,
     pub enable_tracking: bool, // Whether to track this topic for periodic updates
+    pub update_interval_secs: Option<u64>, // Refresh cadence for this topic; unchanged when unset
+    pub max_age_secs: Option<u64>, // Oldest stored news to accept instead of a live fetch; canister default when unset
+    pub force_refresh: Option<bool>, // Always fetch live news
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum NewsSource {
+    // A stored snapshot
+    Cache,
+    // Fetched from the provider for this request
+    Live,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -33,13 +64,88 @@ pub struct NewsResponse {
     pub content: String,
     pub timestamp: u64,
     pub is_tracked: bool,
+    pub status: SnapshotStatus,
+    pub source: NewsSource,
+    // What's new since the previous snapshot, when delta summaries are on
+    pub delta_summary: Option<String>,
+}
+
+impl NewsResponse {
+    fn from_snapshot(topic: String, snapshot: NewsSnapshot, is_tracked: bool, source: NewsSource) -> Self {
+        NewsResponse {
+            topic,
+            status: snapshot.status(),
+            delta_summary: snapshot.delta_summary,
+            content: snapshot.content,
+            timestamp: snapshot.timestamp,
+            is_tracked,
+            source,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum NewsError {
+    Unauthorized(AccessError),
+    InvalidRequest { message: String },
+    TrackingLimitReached { limit: u32 },
+    // The caller is subscribed to as many topics as it may be
+    SubscriptionLimitReached { limit: u32 },
+    // The caller's get_news rate limit is used up until `retry_at`
+    RateLimited { retry_at: u64 },
+    // The topic's provider is paused after repeated errors; nothing was
+    // fetched or recorded
+    ProviderUnavailable { provider: String, retry_at: u64 },
+    // The provider call failed. The failure is kept in the topic's history
+    // as a failed snapshot at `timestamp`.
+    FetchFailed {
+        topic: String,
+        timestamp: u64,
+        code: String,
+        message: String,
+        error: LlmError,
+    },
+}
+
+impl From<AccessError> for NewsError {
+    fn from(error: AccessError) -> Self {
+        NewsError::Unauthorized(error)
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct TrackedTopic {
+    pub topic_id: u64,
     pub topic: String,
     pub last_update: u64,
+    pub last_checked: u64,
+    // Snapshots stored, i.e. refreshes that found something new
     pub update_count: u32,
+    // Successful refreshes, including unchanged ones that stored nothing
+    pub check_count: u32,
+    pub last_error: Option<TopicFailure>,
+    pub update_interval_secs: u64,
+    pub next_update: u64,
+    pub subscriber_count: u32,
+    // Failed refreshes in a row; each one doubles the wait before the next
+    pub consecutive_failures: u32,
+    // State of the topic's provider; while Open no topic on it refreshes
+    pub provider_circuit: CircuitState,
+}
+
+// Structured counterpart of health_check
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HealthStatus {
+    pub cycles_balance: u128,
+    pub cycles_level: CyclesLevel,
+    pub low_cycles_threshold: u128,
+    pub critical_cycles_threshold: u128,
+    pub scheduler: SchedulerStatus,
+    pub snapshot_count: u64,
+    pub tracked_topics: u64,
+    pub provider: ProviderConfig,
+    // Newest first
+    pub recent_alerts: Vec<AlertEvent>,
 }
 
 // Simple data structure for agent operations
@@ -50,327 +156,835 @@ pub struct AgentData {
     pub agent_id: String,
 }
 
-// Initialize canister with timer
+// Initialize canister settings and the scheduler tick
 #[init]
-fn init() {
-    // Set up a timer to run every 20 minutes (1200 seconds)
-    set_timer_interval(Duration::from_secs(1200), || {
-        ic_cdk::spawn(async {
-            update_all_tracked_topics().await;
-        })
-    });
+fn init(args: Option<InitArgs>) {
+    if let Err(message) = config::apply_init_args(args.unwrap_or_default()) {
+        ic_cdk::trap(&format!("Invalid init args: {}", message));
+    }
+    start_scheduler();
+    retention::start();
     
-    ic_cdk::print("News canister initialized with 20-minute update timer");
+    ic_cdk::print("News canister initialized with per-topic update scheduler");
+}
+
+// Stable structures are written through on every call, so there is nothing
+// to restore here beyond migrating data from older layouts and reporting
+// what survived the upgrade. Timers don't survive upgrades, so the scheduler,
+// the compaction job and any search index rebuild are armed again.
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    migrations::run_all();
+    if let Err(message) = config::apply_init_args(args.unwrap_or_default()) {
+        ic_cdk::trap(&format!("Invalid upgrade args: {}", message));
+    }
+    start_scheduler();
+    retention::start();
+    search::resume_rebuild();
+    let snapshot_count = SNAPSHOTS.with(|snapshots| snapshots.borrow().len());
+    let tracked_count = TRACKED_TOPICS.with(|topics| topics.borrow().len());
+
+    ic_cdk::print(format!(
+        "News canister upgraded: restored {} news snapshots and {} tracked topics",
+        snapshot_count, tracked_count
+    ));
+}
+
+// Wake up every tick, refresh whichever topics are due and roll finished
+// days and weeks up into digests
+fn start_scheduler() {
+    scheduler::start(|| {
+        ic_cdk::spawn(update_due_topics());
+        ic_cdk::spawn(generate_due_digests());
+    });
+}
+
+// Whether the update timer is armed and when it next fires
+#[query]
+pub fn get_scheduler_status() -> SchedulerStatus {
+    scheduler::status()
 }
 
 // Main news query function - simplified and focused
 #[update]
-pub async fn get_news(request: NewsQuery) -> NewsResponse {
-    let timestamp = ic_cdk::api::time();
+pub async fn get_news(request: NewsQuery) -> Result<NewsResponse, NewsError> {
+    let role = if request.enable_tracking {
+        access::require_signed_in(Role::Reader)?
+    } else {
+        access::require(Role::Reader)?
+    };
     let topic = request.topic.clone();
-    
-    // Track this topic if requested
-    if request.enable_tracking {
-        TRACKED_TOPICS.with(|topics| {
-            topics.borrow_mut().insert(topic.clone());
-        });
+    topics::validate_topic(&topic)
+        .map_err(|message| NewsError::InvalidRequest { message })?;
+    if let Some(interval_secs) = request.update_interval_secs {
+        // Retiming a shared topic changes what everyone's refreshes cost,
+        // so it takes the same role as set_topic_interval
+        access::require(Role::Operator)?;
+        scheduler::validate_interval(interval_secs)
+            .map_err(|message| NewsError::InvalidRequest { message })?;
     }
+    // Nothing new is registered until the caller has spent a token; known
+    // topics take a new interval straight away
+    let existing = match topics::resolve_topic(&topic) {
+        Some(_) if request.update_interval_secs.is_some() => Some(
+            topics::set_update_interval(&topic, request.update_interval_secs)
+                .map_err(|message| NewsError::InvalidRequest { message })?,
+        ),
+        existing => existing,
+    };
     
-    // Query Groq AI for news
-    let news_content = query_groq_for_news(&topic).await;
-    
-    // Store the result
-    let key = format!("news_{}_{}", sanitize_topic(&topic), timestamp);
-    STORAGE.with(|storage| {
-        storage.borrow_mut().insert(key, news_content.clone());
-    });
+    // Check the caller may subscribe before spending a token
+    let caller = ic_cdk::caller();
+    let subscribe = request.enable_tracking
+        && existing.as_ref().is_none_or(|record| !subscriptions::is_subscribed(record.id, caller));
+    if subscribe {
+        let limit = config::limits().max_tracked_topics;
+        let tracked_count = TRACKED_TOPICS.with(|topics| topics.borrow().len());
+        let already_tracked = existing.as_ref().is_some_and(|record| topics::is_tracked(record.id));
+        if !already_tracked && tracked_count >= limit as u64 {
+            return Err(NewsError::TrackingLimitReached { limit });
+        }
+        quota::can_subscribe(caller, role)
+            .map_err(|limit| NewsError::SubscriptionLimitReached { limit })?;
+    }
     
-    // Update last update time
-    LAST_UPDATE.with(|updates| {
-        updates.borrow_mut().insert(topic.clone(), timestamp);
-    });
+    // Recent enough stored news is served without an outcall or a token
+    let now = ic_cdk::api::time();
+    if let Some(record) = existing.as_ref().filter(|_| !request.force_refresh.unwrap_or(false)) {
+        let max_age_secs = request.max_age_secs
+            .unwrap_or_else(|| config::get().cache_max_age_secs());
+        if let Some(snapshot) = storage::fresh_snapshot(record.id, record.last_checked(), max_age_secs, now) {
+            if subscribe {
+                subscriptions::subscribe(record.id, caller, now);
+            }
+            let is_tracked = topics::is_tracked(record.id);
+            return Ok(NewsResponse::from_snapshot(topic, snapshot, is_tracked, NewsSource::Cache));
+        }
+    }
     
-    // Check if this topic is being tracked
-    let is_tracked = TRACKED_TOPICS.with(|topics| {
-        topics.borrow().contains(&topic)
-    });
+    quota::take(caller, role, Action::GetNews, now)
+        .map_err(|retry_at| NewsError::RateLimited { retry_at })?;
+    let record = match existing {
+        Some(record) => record,
+        None => {
+            let record = topics::get_or_create_topic(&topic);
+            match request.update_interval_secs {
+                Some(_) => topics::set_update_interval(&topic, request.update_interval_secs)
+                    .map_err(|message| NewsError::InvalidRequest { message })?,
+                None => record,
+            }
+        }
+    };
+    if subscribe {
+        subscriptions::subscribe(record.id, caller, now);
+    }
     
-    NewsResponse {
-        topic,
-        content: news_content,
-        timestamp,
-        is_tracked,
+    let snapshot = fetch_news(&record, &topic, caller).await?;
+    Ok(NewsResponse::from_snapshot(topic, snapshot, topics::is_tracked(record.id), NewsSource::Live))
+}
+
+// Fetch live news for a topic, sharing the outcall with any fetch of the
+// same topic that is already in flight
+async fn fetch_news(record: &TopicRecord, topic: &str, caller: Principal) -> Result<NewsSnapshot, NewsError> {
+    coalesce::run(record.id, || fetch_live_news(record, topic, caller)).await
+}
+
+// Query the topic's LLM provider for news and store the outcome. Failures
+// are stored too, as snapshots with a Failed status and no content. The
+// outcall's cycles are charged to the topic and `caller`.
+async fn fetch_live_news(record: &TopicRecord, topic: &str, caller: Principal) -> Result<NewsSnapshot, NewsError> {
+    let provider = llm::provider_config_for(Some(record));
+    let attribution = Attribution { topic_id: Some(record.id), caller };
+    let result = llm::complete(&provider, &llm::news_prompt(topic), attribution).await;
+    let timestamp = ic_cdk::api::time();
+    let tracked = topics::is_tracked(record.id);
+
+    match result {
+        // The provider is down, not this topic: record nothing and try again
+        // once its circuit reopens
+        Err(LlmError::CircuitOpen { provider, retry_at }) => {
+            if tracked {
+                scheduler::schedule_at(record.id, retry_at);
+            }
+            Err(NewsError::ProviderUnavailable { provider, retry_at })
+        }
+        Ok(content) => {
+            // Any fetch, scheduled or not, restarts a tracked topic's interval
+            if tracked {
+                scheduler::reschedule(record, timestamp);
+            }
+            // Unchanged news only counts as a check; the last snapshot stands
+            // and is returned with the check time
+            let config = config::get();
+            let previous = storage::latest_snapshot(record.id, false);
+            if let Some(previous) = previous.as_ref() {
+                if dedupe::is_duplicate(&previous.content, &content, config.duplicate_threshold_percent()) {
+                    topics::record_check(record.id, timestamp, false);
+                    return Ok(dedupe::unchanged(previous, timestamp));
+                }
+            }
+
+            let delta_summary = match previous {
+                Some(previous) if config.delta_summaries() => {
+                    summarize_changes(record, topic, &previous, &content, attribution).await
+                }
+                _ => None,
+            };
+            let snapshot = NewsSnapshot {
+                topic: topic.to_string(),
+                content,
+                timestamp,
+                status: Some(SnapshotStatus::Ok),
+                delta_summary,
+            };
+            storage::insert_snapshot(record.id, snapshot.clone());
+            topics::record_check(record.id, timestamp, true);
+            Ok(snapshot)
+        }
+        Err(error) => {
+            let (code, message) = (error.code(), error.to_string());
+            storage::insert_snapshot(record.id, NewsSnapshot {
+                topic: topic.to_string(),
+                content: String::new(),
+                timestamp,
+                status: Some(SnapshotStatus::Failed {
+                    code: code.clone(),
+                    message: message.clone(),
+                }),
+                delta_summary: None,
+            });
+            let failures = topics::record_failure(record.id, error.clone(), timestamp);
+            if tracked {
+                scheduler::reschedule_with_backoff(record, failures, timestamp);
+            }
+            Err(NewsError::FetchFailed {
+                topic: topic.to_string(),
+                timestamp,
+                code,
+                message,
+                error,
+            })
+        }
     }
 }
 
 // Get all tracked topics
 #[query]
 pub fn get_tracked_topics() -> Vec<TrackedTopic> {
-    TRACKED_TOPICS.with(|topics| {
-        topics.borrow().iter().map(|topic| {
-            let last_update = LAST_UPDATE.with(|updates| {
-                updates.borrow().get(topic).copied().unwrap_or(0)
-            });
-            
+    let topic_ids: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    tracked_topic_details(topic_ids)
+}
+
+// Topics the caller is subscribed to
+#[query]
+pub fn my_topics() -> Vec<TrackedTopic> {
+    let topic_ids = subscriptions::topics_of(ic_cdk::caller())
+        .into_iter()
+        .map(|(topic_id, _)| topic_id)
+        .collect();
+    tracked_topic_details(topic_ids)
+}
+
+fn tracked_topic_details(topic_ids: Vec<u64>) -> Vec<TrackedTopic> {
+    let now = ic_cdk::api::time();
+    
+    topic_ids.into_iter().filter_map(topics::get_topic).map(|record| {
+        TrackedTopic {
+            topic_id: record.id,
+            subscriber_count: subscriptions::subscriber_count(record.id) as u32,
+            update_interval_secs: scheduler::interval_secs(&record),
+            next_update: scheduler::next_due(record.id),
+            consecutive_failures: record.failures(),
+            last_checked: record.last_checked(),
+            check_count: record.check_count(),
+            provider_circuit: circuit::state(llm::provider_config_for(Some(&record)).kind, now),
+            topic: record.display_name,
+            last_update: record.last_update,
             // Count how many updates we have for this topic
-            let update_count = STORAGE.with(|storage| {
-                storage.borrow().keys()
-                    .filter(|k| k.starts_with(&format!("news_{}_", sanitize_topic(topic))))
-                    .count() as u32
-            });
-            
-            TrackedTopic {
-                topic: topic.clone(),
-                last_update,
-                update_count,
-            }
-        }).collect()
-    })
+            update_count: storage::snapshot_count(record.id) as u32,
+            last_error: record.last_error,
+        }
+    }).collect()
 }
 
-// Stop tracking a topic
+// Drop the caller's subscription to a topic. The topic keeps refreshing
+// while anyone else is still subscribed. Operators also drop the canister's
+// own subscription, which legacy topics were migrated to.
 #[update]
-pub fn untrack_topic(topic: String) -> bool {
-    TRACKED_TOPICS.with(|topics| {
-        topics.borrow_mut().remove(&topic)
-    })
+pub fn untrack_topic(topic: String) -> Result<bool, AccessError> {
+    let role = access::require_signed_in(Role::Reader)?;
+    let Some(record) = topics::resolve_topic(&topic) else {
+        return Ok(false);
+    };
+    let mut removed = subscriptions::unsubscribe(record.id, ic_cdk::caller());
+    if role >= Role::Operator {
+        removed |= subscriptions::unsubscribe(record.id, ic_cdk::id());
+    }
+    Ok(removed)
+}
+
+// Change how often a topic is refreshed; `None` restores the default cadence
+#[update]
+pub fn set_topic_interval(topic: String, update_interval_secs: Option<u64>) -> Result<TopicRecord, String> {
+    access::require(Role::Operator)?;
+    topics::set_update_interval(&topic, update_interval_secs)
 }
 
-// Get latest news for a specific topic (from storage)
+// Get latest news for a specific topic (from storage). Failed fetches are
+// skipped unless `include_failures` is set.
 #[query]
-pub fn get_latest_stored_news(topic: String) -> Option<NewsResponse> {
-    let sanitized = sanitize_topic(&topic);
-    let prefix = format!("news_{}_", sanitized);
-    
-    // Find all entries for this topic
-    let mut entries: Vec<(String, String, u64)> = STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(k, _)| k.starts_with(&prefix))
-            .map(|(k, v)| {
-                // Extract timestamp from key
-                let parts: Vec<&str> = k.split('_').collect();
-                let timestamp = parts.last()
-                    .and_then(|t| t.parse::<u64>().ok())
-                    .unwrap_or(0);
-                (k.clone(), v.clone(), timestamp)
-            })
-            .collect()
-    });
+pub fn get_latest_stored_news(topic: String, include_failures: Option<bool>) -> Option<NewsResponse> {
+    let record = topics::resolve_topic(&topic)?;
+    let snapshot = storage::latest_snapshot(record.id, include_failures.unwrap_or(false))?;
     
-    // Sort by timestamp, newest first
-    entries.sort_by(|a, b| b.2.cmp(&a.2));
-    
-    // Return the most recent
-    entries.first().map(|(_, content, timestamp)| {
-        NewsResponse {
-            topic: topic.clone(),
-            content: content.clone(),
-            timestamp: *timestamp,
-            is_tracked: TRACKED_TOPICS.with(|topics| topics.borrow().contains(&topic)),
-        }
-    })
+    Some(NewsResponse::from_snapshot(topic, snapshot, topics::is_tracked(record.id), NewsSource::Cache))
 }
 
-// Get news history for a topic
+// Get news history for a topic. Failed fetches are skipped unless
+// `include_failures` is set.
 #[query]
-pub fn get_news_history(topic: String, limit: Option<u32>) -> Vec<NewsResponse> {
-    let sanitized = sanitize_topic(&topic);
-    let prefix = format!("news_{}_", sanitized);
+pub fn get_news_history(topic: String, limit: Option<u32>, include_failures: Option<bool>) -> Vec<NewsResponse> {
     let limit = limit.unwrap_or(10) as usize;
+    let Some(record) = topics::resolve_topic(&topic) else {
+        return Vec::new();
+    };
+    let is_tracked = topics::is_tracked(record.id);
     
-    // Find all entries for this topic
-    let mut entries: Vec<(String, String, u64)> = STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(k, _)| k.starts_with(&prefix))
-            .map(|(k, v)| {
-                let parts: Vec<&str> = k.split('_').collect();
-                let timestamp = parts.last()
-                    .and_then(|t| t.parse::<u64>().ok())
-                    .unwrap_or(0);
-                (k.clone(), v.clone(), timestamp)
+    // Newest first, straight from the topic's key range
+    storage::snapshot_history(record.id, limit, include_failures.unwrap_or(false))
+        .into_iter()
+        .map(|snapshot| NewsResponse::from_snapshot(topic.clone(), snapshot, is_tracked, NewsSource::Cache))
+        .collect()
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    OldestFirst,
+    NewestFirst,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HistoryQuery {
+    pub topic: String,
+    // `next_cursor` from the previous page; the first page when unset
+    pub cursor: Option<u64>,
+    // Page size, default 20 and at most 100
+    pub limit: Option<u32>,
+    // Inclusive timestamp bounds
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    // Newest first by default
+    pub order: Option<SortOrder>,
+    pub include_failures: Option<bool>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HistoryPage {
+    pub entries: Vec<NewsResponse>,
+    // Entries matching the filters across all pages
+    pub total: u64,
+    // Pass back as `cursor` for the next page; unset on the last page
+    pub next_cursor: Option<u64>,
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+// Diffs returned by one get_topic_changes call
+const MAX_TOPIC_CHANGES: usize = 50;
+
+// One page of a topic's history, for timelines that load more as they
+// scroll. The cursor is the timestamp of the last entry returned.
+#[query]
+pub fn get_news_page(query: HistoryQuery) -> HistoryPage {
+    let Some(record) = topics::resolve_topic(&query.topic) else {
+        return HistoryPage { entries: Vec::new(), total: 0, next_cursor: None };
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let include_failures = query.include_failures.unwrap_or(false);
+    let descending = query.order.unwrap_or(SortOrder::NewestFirst) == SortOrder::NewestFirst;
+    let since = query.since.unwrap_or(0);
+    let until = query.until.unwrap_or(u64::MAX);
+
+    // The page starts just past the cursor
+    let (from, to) = match query.cursor {
+        Some(cursor) if descending => (since, until.min(cursor.saturating_sub(1))),
+        Some(cursor) => (since.max(cursor.saturating_add(1)), until),
+        None => (since, until),
+    };
+    let (snapshots, has_more) = storage::snapshot_range(
+        record.id, from, to, descending, limit, include_failures,
+    );
+    let next_cursor = if has_more {
+        snapshots.last().map(|snapshot| snapshot.timestamp)
+    } else {
+        None
+    };
+    let is_tracked = topics::is_tracked(record.id);
+
+    HistoryPage {
+        total: storage::count_snapshots(record.id, since, until, include_failures),
+        entries: snapshots.into_iter()
+            .map(|snapshot| {
+                NewsResponse::from_snapshot(query.topic.clone(), snapshot, is_tracked, NewsSource::Cache)
             })
-            .collect()
-    });
-    
-    // Sort by timestamp, newest first
-    entries.sort_by(|a, b| b.2.cmp(&a.2));
-    
-    // Take only the requested limit
-    entries.truncate(limit);
-    
-    // Convert to NewsResponse
-    entries.into_iter().map(|(_, content, timestamp)| {
-        NewsResponse {
-            topic: topic.clone(),
-            content,
-            timestamp,
-            is_tracked: TRACKED_TOPICS.with(|topics| topics.borrow().contains(&topic)),
+            .collect(),
+        next_cursor,
+    }
+}
+
+// Full-text search across every topic's stored news, best matches first
+#[query]
+pub fn search_news(query: String, filters: Option<SearchFilters>, page: Option<SearchPage>) -> Result<SearchResults, String> {
+    search::search(&query, &filters.unwrap_or_default(), &page.unwrap_or_default())
+}
+
+// A topic's daily or weekly digests whose period started within `range`,
+// newest first
+#[query]
+pub fn get_digests(topic: String, period: DigestPeriod, range: Option<TimeRange>) -> Vec<Digest> {
+    let Some(record) = topics::resolve_topic(&topic) else {
+        return Vec::new();
+    };
+    digests::range(record.id, period, &range.unwrap_or_default())
+}
+
+// Most recent changes to a topic, newest first: each entry compares a
+// successful snapshot taken after `since` with the one before it
+#[query]
+pub fn get_topic_changes(topic: String, since: Option<u64>) -> Vec<SnapshotDiff> {
+    let Some(record) = topics::resolve_topic(&topic) else {
+        return Vec::new();
+    };
+    let (mut snapshots, _) = storage::snapshot_range(
+        record.id, since.unwrap_or(0), u64::MAX, true, MAX_TOPIC_CHANGES, false,
+    );
+    // The oldest snapshot in range is compared with its predecessor
+    if let Some(oldest) = snapshots.last() {
+        let (previous, _) = storage::snapshot_range(
+            record.id, 0, oldest.timestamp.saturating_sub(1), true, 1, false,
+        );
+        snapshots.extend(previous);
+    }
+
+    snapshots.windows(2).map(|pair| {
+        let (newer, older) = (&pair[0], &pair[1]);
+        let changes = diff::diff(&older.content, &newer.content);
+        SnapshotDiff {
+            from_timestamp: older.timestamp,
+            to_timestamp: newer.timestamp,
+            added: changes.added,
+            removed: changes.removed,
+            unchanged: changes.unchanged,
+            delta_summary: newer.delta_summary.clone(),
         }
     }).collect()
 }
 
-// Function to update all tracked topics (called by timer)
-async fn update_all_tracked_topics() {
-    let topics: Vec<String> = TRACKED_TOPICS.with(|topics| {
-        topics.borrow().iter().cloned().collect()
-    });
-    
-    ic_cdk::print(format!("Timer triggered: Updating {} tracked topics", topics.len()));
-    
-    for topic in topics {
-        let news_content = query_groq_for_news(&topic).await;
-        let timestamp = ic_cdk::api::time();
-        
-        // Store the update
-        let key = format!("news_{}_{}", sanitize_topic(&topic), timestamp);
-        STORAGE.with(|storage| {
-            storage.borrow_mut().insert(key, news_content);
-        });
-        
-        // Update last update time
-        LAST_UPDATE.with(|updates| {
-            updates.borrow_mut().insert(topic.clone(), timestamp);
-        });
-        
-        ic_cdk::print(format!("Updated news for topic: {}", topic));
+// Look up a topic's registry entry by name or alias
+#[query]
+pub fn get_topic(topic: String) -> Option<TopicRecord> {
+    topics::resolve_topic(&topic)
+}
+
+// List every registered topic
+#[query]
+pub fn list_topics() -> Vec<TopicRecord> {
+    storage::TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(_, record)| record).collect()
+    })
+}
+
+// Make another spelling resolve to an existing topic
+#[update]
+pub fn add_topic_alias(topic: String, alias: String) -> Result<TopicRecord, String> {
+    access::require(Role::Operator)?;
+    topics::add_alias(&topic, &alias)
+}
+
+// Fold one topic's history and names into another
+#[update]
+pub fn merge_topics(source: String, target: String) -> Result<TopicRecord, String> {
+    access::require(Role::Operator)?;
+    topics::merge_topics(&source, &target)
+}
+
+// Ask the topic's provider what is new in `content` compared with the
+// previous snapshot. No summary when nothing was added or the call fails;
+// the news itself is stored either way.
+async fn summarize_changes(
+    record: &TopicRecord,
+    topic: &str,
+    previous: &NewsSnapshot,
+    content: &str,
+    attribution: Attribution,
+) -> Option<String> {
+    let changes = diff::diff(&previous.content, content);
+    if changes.added.is_empty() {
+        return None;
     }
+    let provider = llm::provider_config_for(Some(record));
+    llm::complete(&provider, &llm::delta_prompt(topic, &changes.added), attribution).await.ok()
 }
 
-// Query Groq AI for news about a topic
-async fn query_groq_for_news(topic: &str) -> String {
-    let api_url = "https://api.groq.com/openai/v1/chat/completions";
-    
-    let prompt = format!(
-        "Give me the latest news and updates about '{}'. Please provide: \
-        1) Recent headlines (if any) \
-        2) Key developments \
-        3) Important facts or trends \
-        Keep it concise, factual, and well-organized.",
-        topic
-    );
+// Refresh the tracked topics whose interval has elapsed (called by timer)
+async fn update_due_topics() {
+    // Topics stay due while paused and refresh once cycles are topped up
+    if watchdog::check() == CyclesLevel::Critical {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    let topic_ids = scheduler::due_topics(now);
+    if topic_ids.is_empty() {
+        return;
+    }
+    // Due topics stay due, so they refresh once the next day's budget opens
+    if costs::budget_exhausted(now) {
+        ic_cdk::print("Daily cycle budget exhausted: scheduled updates paused");
+        return;
+    }
     
-    let json_payload = format!(
-        r#"{{"model": "llama3-8b-8192", "messages": [{{"role": "user", "content": "{}"}}], "max_tokens": 300}}"#,
-        prompt.replace("\"", "\\\"")
-    );
-
-    let request_headers = vec![
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        },
-        HttpHeader {
-            name: "Authorization".to_string(),
-            value: "Bearer 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".to_string(),
-        },
-    ];
-
-    let http_req = CanisterHttpRequestArgument {
-        url: api_url.to_string(),
-        method: HttpMethod::POST,
-        body: Some(json_payload.as_bytes().to_vec()),
-        max_response_bytes: Some(4096),
-        transform: None,
-        headers: request_headers,
-    };
+    ic_cdk::print(format!("Timer triggered: Updating {} due topics", topic_ids.len()));
+    // Scheduled refreshes are charged to the canister itself
+    match cycle::run(CycleTrigger::Scheduled, ic_cdk::id(), topic_ids).await {
+        Ok(report) => ic_cdk::print(format!(
+            "Update cycle {} finished: {} succeeded, {} failed",
+            report.cycle_id, report.succeeded, report.failed
+        )),
+        // Due topics stay due, so the next free tick picks them up
+        Err(message) => ic_cdk::print(format!("Skipping tick: {}", message)),
+    }
+}
 
-    match http_request(http_req, 25_000_000_000).await {
-        Ok((response,)) => {
-            let response_body = String::from_utf8_lossy(&response.body);
-            
-            // Try to parse and extract the content
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&response_body) {
-                if let Some(content) = json["choices"][0]["message"]["content"].as_str() {
-                    return content.to_string();
-                }
-            }
-            
-            // Return the full response if parsing fails
-            response_body.to_string()
-        }
-        Err((r, m)) => {
-            format!("Failed to fetch news: {:?} - {}", r, m)
+// Digests are a nicety, so they only run while cycles are plentiful and the
+// day's budget has room; missed ones are picked up by a later tick
+async fn generate_due_digests() {
+    let now = ic_cdk::api::time();
+    if watchdog::current_level() != CyclesLevel::Normal || costs::budget_exhausted(now) {
+        return;
+    }
+    let topic_ids: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    for (topic_id, period, period_start) in digests::take_due(&topic_ids, now) {
+        let attribution = Attribution { topic_id: Some(topic_id), caller: ic_cdk::id() };
+        match digests::generate(topic_id, period, period_start, attribution).await {
+            Ok(digest) => ic_cdk::print(format!(
+                "Stored {:?} digest for '{}' from {} snapshots",
+                period, digest.topic, digest.snapshot_count
+            )),
+            Err(message) => ic_cdk::print(format!(
+                "{:?} digest for topic {} failed: {}", period, topic_id, message
+            )),
         }
     }
 }
 
-// Helper function to sanitize topic names for storage keys
-fn sanitize_topic(topic: &str) -> String {
-    topic.to_lowercase()
-        .chars()
-        .map(|c| if c.is_alphanumeric() { c } else { '_' })
-        .collect()
+// Refresh every tracked topic regardless of schedule
+async fn update_all_tracked_topics() -> Result<CycleReport, String> {
+    let topic_ids: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    
+    ic_cdk::print(format!("Manual update: Updating {} tracked topics", topic_ids.len()));
+    cycle::run(CycleTrigger::Manual, ic_cdk::caller(), topic_ids).await
+}
+
+// Cycles spent on outcalls per topic, caller and day, against the budget
+#[query]
+pub fn get_cost_report() -> CostReport {
+    costs::report(ic_cdk::api::time())
+}
+
+// Report of a past update cycle by id, or of the most recent one
+#[query]
+pub fn get_cycle_report(cycle_id: Option<u64>) -> Option<CycleReport> {
+    cycle::report(cycle_id)
 }
 
 // Legacy support functions (keeping your existing interface)
 #[update]
-pub async fn agent_query_groq(request: GroqQueryRequest) -> String {
-    let result = query_groq_for_news(&request.prompt).await;
+pub async fn agent_query_groq(request: GroqQueryRequest) -> Result<String, AccessError> {
+    let role = access::require(Role::Agent)?;
+    let caller = ic_cdk::caller();
+    quota::take(caller, role, Action::AgentQuery, ic_cdk::api::time())
+        .map_err(|retry_at| AccessError::RateLimited { retry_at })?;
+    let provider = llm::provider_config_for(None);
+    let attribution = Attribution { topic_id: None, caller };
+    let result = match llm::complete(&provider, &llm::news_prompt(&request.prompt), attribution).await {
+        Ok(result) => result,
+        Err(error) => {
+            return Ok(format!("Agent {} failed to query news: {}", request.agent_id, error));
+        }
+    };
     
     // Store in legacy format
     STORAGE.with(|storage| {
-        storage.borrow_mut().insert(request.store_key.clone(), result.clone());
+        storage.borrow_mut().insert(request.store_key.clone(), result);
     });
     
-    format!("Agent {} successfully queried news and stored under key: {}", 
-            request.agent_id, request.store_key)
+    Ok(format!("Agent {} successfully queried news and stored under key: {}", 
+            request.agent_id, request.store_key))
 }
 
 #[update]
-pub fn agent_store_data(data: AgentData) -> String {
+pub fn agent_store_data(data: AgentData) -> Result<String, AccessError> {
+    access::require(Role::Agent)?;
     STORAGE.with(|storage| {
         storage.borrow_mut().insert(data.key.clone(), data.value.clone());
     });
     
-    format!("Agent {} stored data with key: {}", data.agent_id, data.key)
+    Ok(format!("Agent {} stored data with key: {}", data.agent_id, data.key))
 }
 
 #[query]
 pub fn agent_get_data(key: String) -> Option<String> {
     STORAGE.with(|storage| {
-        storage.borrow().get(&key).cloned()
+        storage.borrow().get(&key)
     })
 }
 
 #[query]
 pub fn get_all_data() -> Vec<(String, String)> {
     STORAGE.with(|storage| {
-        storage.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        storage.borrow().iter().collect()
     })
 }
 
 #[update]
-pub fn clear_storage() -> String {
-    STORAGE.with(|storage| {
-        storage.borrow_mut().clear();
-    });
-    TRACKED_TOPICS.with(|topics| {
-        topics.borrow_mut().clear();
-    });
-    LAST_UPDATE.with(|updates| {
-        updates.borrow_mut().clear();
-    });
-    "Storage and tracked topics cleared".to_string()
+pub fn clear_storage() -> Result<String, AccessError> {
+    access::require(Role::Admin)?;
+    storage::clear_all();
+    Ok("Storage and tracked topics cleared".to_string())
 }
 
 #[query]
 pub fn health_check() -> String {
+    let snapshot_count = SNAPSHOTS.with(|snapshots| snapshots.borrow().len());
     let storage_count = STORAGE.with(|storage| storage.borrow().len());
     let tracked_count = TRACKED_TOPICS.with(|topics| topics.borrow().len());
+    let provider = config::get().provider;
+    let configured = credentials::configured_providers();
+    let credentials_status = if configured.is_empty() {
+        "none".to_string()
+    } else {
+        configured.iter()
+            .map(|(name, updated_at)| format!("{} (last set at {})", name, updated_at))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    
+    let balance = ic_cdk::api::canister_balance128();
+    let alert_status = match watchdog::recent_alerts(1).pop() {
+        Some(alert) => format!(" Last alert at {}: {}.", alert.timestamp, alert.message),
+        None => String::new(),
+    };
     
     format!(
-        "News canister is running. {} items in storage, {} topics being tracked for updates.",
-        storage_count, tracked_count
+        "News canister is running. {} news snapshots and {} agent data items in storage, {} topics being tracked for updates. Provider: {} ({}). Credentials: {}. Cycles: {} ({:?}).{}",
+        snapshot_count, storage_count, tracked_count, provider.kind.name(), provider.model, credentials_status,
+        balance, watchdog::level_for(balance), alert_status
     )
 }
 
+// Cycles balance, watchdog level and alerts, and scheduler state
+#[query]
+pub fn get_health_status() -> HealthStatus {
+    let limits = config::limits();
+    let cycles_balance = ic_cdk::api::canister_balance128();
+    HealthStatus {
+        cycles_balance,
+        cycles_level: watchdog::level_for(cycles_balance),
+        low_cycles_threshold: limits.low_cycles_threshold(),
+        critical_cycles_threshold: limits.critical_cycles_threshold(),
+        scheduler: scheduler::status(),
+        snapshot_count: SNAPSHOTS.with(|snapshots| snapshots.borrow().len()),
+        tracked_topics: TRACKED_TOPICS.with(|topics| topics.borrow().len()),
+        provider: config::get().provider,
+        recent_alerts: watchdog::recent_alerts(10),
+    }
+}
+
+// Store or rotate an LLM provider API key (Groq unless `provider` is given).
+// Admins only; the key is kept in stable memory and never returned by
+// any endpoint.
+#[update]
+pub fn set_provider_credentials(api_key: String, provider: Option<ProviderKind>) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    let provider = provider.unwrap_or(ProviderKind::Groq);
+    credentials::set_api_key(provider, api_key)?;
+    ic_cdk::print(format!("Provider credentials updated for {}", provider.name()));
+    Ok(())
+}
+
+// Canister-wide LLM provider used by every topic without an override
+#[query]
+pub fn get_provider_config() -> ProviderConfig {
+    config::get().provider
+}
+
+#[update]
+pub fn set_provider_config(provider: ProviderConfig) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    provider.validate()?;
+    config::update(|config| config.provider = provider);
+    Ok(())
+}
+
+// Pin a topic to its own provider settings, or clear the override with null
+#[update]
+pub fn set_topic_provider(topic: String, provider: Option<ProviderConfig>) -> Result<TopicRecord, String> {
+    access::require(Role::Admin)?;
+    if let Some(provider) = &provider {
+        provider.validate()?;
+    }
+    topics::set_provider(&topic, provider)
+}
+
+// Cap the cycles the scheduler's updates and digests may spend per UTC day,
+// or lift the cap with null. Calls made on behalf of users and agents are
+// not counted against it.
+#[update]
+pub fn set_daily_cycle_budget(budget: Option<u128>) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    config::update(|config| {
+        let mut limits = config.limits.clone().unwrap_or_default();
+        limits.daily_cycle_budget = budget;
+        config.limits = Some(limits);
+    });
+    Ok(())
+}
+
+// How old cached news get_news may serve by default; null restores the
+// 5-minute default and 0 turns the cache off
+#[update]
+pub fn set_cache_max_age(max_age_secs: Option<u64>) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    config::update(|config| config.cache_max_age_secs = max_age_secs);
+    Ok(())
+}
+
+// Turn LLM summaries of what changed between snapshots on or off
+#[update]
+pub fn set_delta_summaries(enabled: bool) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    config::update(|config| config.delta_summaries = Some(enabled));
+    Ok(())
+}
+
+// Similarity (percent, shingled Jaccard) at which a refresh counts as
+// unchanged and isn't stored; null restores the default of 90
+#[update]
+pub fn set_duplicate_threshold(threshold_percent: Option<u32>) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    if threshold_percent.is_some_and(|percent| percent == 0 || percent > 100) {
+        return Err("Duplicate threshold must be between 1 and 100 percent".to_string());
+    }
+    config::update(|config| config.duplicate_threshold_percent = threshold_percent);
+    Ok(())
+}
+
+// Replace the canister-wide retention policy; unset fields go back to their
+// defaults
+#[update]
+pub fn set_retention_policy(policy: RetentionPolicy) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    policy.validate()?;
+    config::update(|config| config.retention = Some(policy));
+    Ok(())
+}
+
+// Override parts of the retention policy for one topic, or drop the override
+// with null
+#[update]
+pub fn set_topic_retention(topic: String, policy: Option<RetentionPolicy>) -> Result<TopicRecord, String> {
+    access::require(Role::Admin)?;
+    topics::set_retention(&topic, policy)
+}
+
+// Dry run of history compaction: what the retention policy would prune now,
+// for one topic or a page of topics starting at `cursor`
+#[query]
+pub fn preview_retention(topic: Option<String>, cursor: Option<u64>) -> Result<RetentionPage, String> {
+    let now = ic_cdk::api::time();
+    match topic {
+        Some(topic) => {
+            let record = topics::resolve_topic(&topic)
+                .ok_or_else(|| format!("Unknown topic: {}", topic))?;
+            Ok(RetentionPage { reports: vec![retention::report(record, now)], next_cursor: None })
+        }
+        None => Ok(retention::preview_page(cursor.unwrap_or(0), now)),
+    }
+}
+
+// Replace the per-caller rate limits; unset fields go back to their defaults
+#[update]
+pub fn set_rate_limits(rate_limits: RateLimits) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    rate_limits.validate()?;
+    config::update(|config| {
+        let mut limits = config.limits.clone().unwrap_or_default();
+        limits.rate_limits = Some(rate_limits);
+        config.limits = Some(limits);
+    });
+    Ok(())
+}
+
+// The caller's remaining allowance on rate-limited endpoints and topics
+#[query]
+pub fn get_my_quota() -> QuotaStatus {
+    let caller = ic_cdk::caller();
+    let role = access::role_of(caller, ic_cdk::api::is_controller(&caller));
+    quota::status(caller, role, ic_cdk::api::time())
+}
+
+fn require_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("Only canister controllers can manage roles".to_string())
+    }
+}
+
+// Give a principal a role, replacing any role it had. Controllers only.
+#[update]
+pub fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    require_controller()?;
+    access::grant(principal, role, ic_cdk::caller(), ic_cdk::api::time());
+    Ok(())
+}
+
+// Take a principal's role away, leaving it a reader. Controllers only.
+#[update]
+pub fn revoke_role(principal: Principal) -> Result<bool, String> {
+    require_controller()?;
+    Ok(access::revoke(principal))
+}
+
+#[query]
+pub fn get_my_role() -> Role {
+    let caller = ic_cdk::caller();
+    access::role_of(caller, ic_cdk::api::is_controller(&caller))
+}
+
+// Every explicit grant; callers not listed are readers
+#[query]
+pub fn list_roles() -> Vec<RoleGrant> {
+    access::grants()
+}
+
 // Manual trigger for testing
 #[update]
-pub async fn trigger_update_cycle() -> String {
-    update_all_tracked_topics().await;
-    "Manual update cycle completed".to_string()
+pub async fn trigger_update_cycle() -> Result<String, AccessError> {
+    access::require(Role::Operator)?;
+    Ok(match update_all_tracked_topics().await {
+        Ok(report) => format!(
+            "Manual update cycle {} completed: {} succeeded, {} failed",
+            report.cycle_id, report.succeeded, report.failed
+        ),
+        Err(message) => message,
+    })
 }
 
 // Types needed for legacy compatibility
@@ -390,8 +1004,15 @@ pub struct HttpQueryRequest {
 
 // Stub for HTTP agent (not used for news)
 #[update]
-pub async fn agent_query_http(_request: HttpQueryRequest) -> String {
-    "HTTP agent not used for news queries. Use get_news() instead.".to_string()
+pub async fn agent_query_http(_request: HttpQueryRequest) -> Result<String, AccessError> {
+    access::require(Role::Agent)?;
+    Ok("HTTP agent not used for news queries. Use get_news() instead.".to_string())
+}
+
+// HTTP outcall transform, see llm::transform_response
+#[query]
+fn transform_llm_response(args: TransformArgs) -> HttpResponse {
+    llm::transform_response(args)
 }
 
 export_candid!();
\ No newline at end of file
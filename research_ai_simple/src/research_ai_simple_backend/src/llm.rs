@@ -0,0 +1,152 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+    TransformArgs, TransformContext,
+};
+use llm_providers::{normalize_response_body, provider_for, LlmError, ProviderCall, ProviderConfig};
+
+use crate::circuit;
+use crate::config;
+use crate::costs::{self, Attribution};
+use crate::credentials;
+use crate::topics::TopicRecord;
+
+// Name of the canister query every LLM outcall response is passed through
+pub const TRANSFORM_METHOD: &str = "transform_llm_response";
+
+// Provider settings for a topic: its own override, else the canister default
+pub fn provider_config_for(topic: Option<&TopicRecord>) -> ProviderConfig {
+    topic
+        .and_then(|record| record.provider.clone())
+        .unwrap_or_else(|| config::get().provider)
+}
+
+pub fn news_prompt(topic: &str) -> String {
+    format!(
+        "Give me the latest news and updates about '{}'. Please provide: \
+        1) Recent headlines (if any) \
+        2) Key developments \
+        3) Important facts or trends \
+        Keep it concise, factual, and well-organized.",
+        topic
+    )
+}
+
+pub fn delta_prompt(topic: &str, added: &[String]) -> String {
+    format!(
+        "These statements are new in the latest news update about '{}':\n{}\n\
+        In two or three sentences, summarize only the new developments. \
+        Don't repeat background or restate older news.",
+        topic,
+        added.join("\n")
+    )
+}
+
+// `updates` are (timestamp, content) pairs sampled across the period
+pub fn digest_prompt(topic: &str, period: &str, updates: &[(u64, String)]) -> String {
+    let updates: Vec<String> = updates.iter()
+        .map(|(timestamp, content)| format!("[{}]\n{}", timestamp, content))
+        .collect();
+    format!(
+        "Below are news updates about '{}' collected over the past {}, oldest first, \
+        each headed by its timestamp in nanoseconds:\n\n{}\n\n\
+        Write a digest of the {}: the main developments and how the story moved, \
+        in at most six short bullet points. Leave out repeated background.",
+        topic,
+        period,
+        updates.join("\n\n"),
+        period
+    )
+}
+
+// Ask the configured provider for a completion of `prompt`, charging the
+// outcall's cycles to `attribution`
+pub async fn complete(
+    config: &ProviderConfig,
+    prompt: &str,
+    attribution: Attribution,
+) -> Result<String, LlmError> {
+    let provider = provider_for(config);
+    let api_key = credentials::api_key(config.kind);
+    if config.kind.requires_api_key() && api_key.is_none() {
+        return Err(LlmError::MissingCredentials {
+            provider: config.kind.name().to_string(),
+        });
+    }
+
+    let call = match provider.prepare(prompt, api_key.as_deref()) {
+        ProviderCall::Immediate(content) => return Ok(content),
+        ProviderCall::Http(call) => call,
+    };
+    circuit::check(config.kind, ic_cdk::api::time())?;
+
+    let request_bytes = call.url.len()
+        + call.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+        + call.body.len()
+        + TRANSFORM_METHOD.len();
+    let cycles = costs::outcall_cost(request_bytes as u64, call.max_response_bytes);
+
+    let http_req = CanisterHttpRequestArgument {
+        url: call.url,
+        method: HttpMethod::POST,
+        body: Some(call.body),
+        max_response_bytes: Some(call.max_response_bytes),
+        transform: Some(TransformContext::from_name(TRANSFORM_METHOD.to_string(), vec![])),
+        headers: call.headers.into_iter()
+            .map(|(name, value)| HttpHeader { name, value })
+            .collect(),
+    };
+
+    let result = http_request(http_req, cycles).await;
+    // Read the refund before anything else can make a call
+    let spent = cycles.saturating_sub(ic_cdk::api::call::msg_cycles_refunded128());
+    costs::charge(attribution, spent, ic_cdk::api::time());
+
+    let result = match result {
+        Ok((response,)) => {
+            let status = u16::try_from(response.status.0).unwrap_or(u16::MAX);
+            provider.parse_response(status, &response.body)
+        }
+        Err((code, message)) => Err(LlmError::Transport {
+            code: format!("{:?}", code),
+            message,
+        }),
+    };
+    circuit::record(config.kind, &result, ic_cdk::api::time());
+    result
+}
+
+// Make replica responses comparable for consensus: headers (dates, request
+// ids, rate-limit counters) are dropped and the body loses its per-request
+// fields. The status is kept so provider errors still classify correctly.
+pub fn transform_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: normalize_response_body(&args.response.body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_drops_headers_and_keeps_status() {
+        let args = TransformArgs {
+            response: HttpResponse {
+                status: candid::Nat::from(429u32),
+                headers: vec![
+                    HttpHeader { name: "date".to_string(), value: "Tue, 25 Jun 2024 10:58:32 GMT".to_string() },
+                    HttpHeader { name: "x-request-id".to_string(), value: "req_01j16b0w2mfv5r6e9q1w7tn2k3".to_string() },
+                ],
+                body: br#"{"error":{"message":"Rate limit reached","type":"tokens","code":"rate_limit_exceeded"}}"#.to_vec(),
+            },
+            context: vec![],
+        };
+
+        let transformed = transform_response(args);
+        assert_eq!(transformed.status, candid::Nat::from(429u32));
+        assert!(transformed.headers.is_empty());
+        assert!(String::from_utf8(transformed.body).unwrap().contains("rate_limit_exceeded"));
+    }
+}
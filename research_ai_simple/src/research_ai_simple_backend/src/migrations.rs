@@ -0,0 +1,192 @@
+// Upgrade migrations from earlier stable layouts. Each step is idempotent,
+// so post_upgrade can run all of them unconditionally.
+use std::collections::HashMap;
+
+use candid::Principal;
+use llm_providers::ProviderKind;
+
+use crate::storage::{
+    self, NewsSnapshot, CREDENTIALS, LEGACY_CREDENTIALS, LEGACY_LAST_UPDATE, LEGACY_TRACKED_TOPICS,
+    SNAPSHOTS, STORAGE, TOPICS, TOPIC_INDEX, TRACKED_TOPICS,
+};
+use crate::search;
+use crate::subscriptions;
+use crate::topics;
+
+// Prefix of the string keys news used to be stored under in STORAGE
+const LEGACY_NEWS_PREFIX: &str = "news_";
+
+pub fn run_all() {
+    let reindexed = migrate_sanitized_topic_index();
+    let tracked = migrate_legacy_tracking();
+    // Before legacy news moves over, so the rebuild indexes it too
+    let rebuilding = search::start_rebuild();
+    let news = migrate_legacy_news();
+    migrate_legacy_credentials();
+    let unowned = migrate_unowned_tracking();
+
+    if reindexed + tracked + news + unowned > 0 {
+        ic_cdk::print(format!(
+            "Migrated {} topic ids, {} tracked topics, {} legacy news entries and {} unowned tracked topics",
+            reindexed, tracked, news, unowned
+        ));
+    }
+    if rebuilding {
+        ic_cdk::print("Indexing stored news for search in the background");
+    }
+}
+
+// The first snapshot-store layout indexed topic ids by sanitized name with no
+// registry records. Rebuild records for those ids, taking the display name
+// from the newest snapshot, and re-index them by normalized name.
+fn migrate_sanitized_topic_index() -> usize {
+    let registry_empty = TOPICS.with(|topics| topics.borrow().is_empty());
+    if !registry_empty {
+        return 0;
+    }
+
+    let old_index: Vec<(String, u64)> = TOPIC_INDEX.with(|index| index.borrow().iter().collect());
+    TOPIC_INDEX.with(|index| index.borrow_mut().clear_new());
+
+    for (sanitized, id) in &old_index {
+        let latest = storage::latest_snapshot(*id, true);
+        let name = latest.as_ref().map(|s| s.topic.as_str()).unwrap_or(sanitized);
+        let created_at = SNAPSHOTS.with(|snapshots| {
+            snapshots.borrow()
+                .range((*id, 0)..=(*id, u64::MAX))
+                .next()
+                .map(|((_, timestamp), _)| timestamp)
+                .unwrap_or(0)
+        });
+        let record = topics::register_topic(*id, name, created_at);
+        topics::record_update(record.id, latest.map(|s| s.timestamp).unwrap_or(0));
+    }
+    old_index.len()
+}
+
+// Tracking and last-update times used to be keyed by the raw topic string
+fn migrate_legacy_tracking() -> usize {
+    let tracked: Vec<String> = LEGACY_TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(topic, _)| topic).collect()
+    });
+    for topic in &tracked {
+        let record = topics::get_or_create_topic(topic);
+        TRACKED_TOPICS.with(|topics| topics.borrow_mut().insert(record.id, ()));
+    }
+
+    let updates: Vec<(String, u64)> = LEGACY_LAST_UPDATE.with(|updates| {
+        updates.borrow().iter().collect()
+    });
+    for (topic, timestamp) in updates {
+        let record = topics::get_or_create_topic(&topic);
+        topics::record_update(record.id, record.last_update.max(timestamp));
+    }
+
+    LEGACY_TRACKED_TOPICS.with(|topics| topics.borrow_mut().clear_new());
+    LEGACY_LAST_UPDATE.with(|updates| updates.borrow_mut().clear_new());
+    tracked.len()
+}
+
+// Move news written under `news_{topic}_{timestamp}` keys in STORAGE into
+// the snapshot store
+fn migrate_legacy_news() -> usize {
+    let legacy: Vec<(String, String)> = STORAGE.with(|storage| {
+        storage.borrow()
+            .range(LEGACY_NEWS_PREFIX.to_string()..)
+            .take_while(|(k, _)| k.starts_with(LEGACY_NEWS_PREFIX))
+            .collect()
+    });
+    if legacy.is_empty() {
+        return 0;
+    }
+
+    // Legacy keys only carry the sanitized topic, so attach them to whichever
+    // registered topic sanitizes to the same string
+    let mut by_sanitized: HashMap<String, u64> = TOPICS.with(|topics| {
+        topics.borrow().iter()
+            .map(|(id, record)| (sanitize_topic(&record.display_name), id))
+            .collect()
+    });
+
+    let mut migrated = 0;
+    for (key, content) in legacy {
+        // The timestamp is always the last segment; everything between the
+        // prefix and it is the sanitized topic, underscores included.
+        let Some((topic, timestamp)) = key[LEGACY_NEWS_PREFIX.len()..].rsplit_once('_') else {
+            continue;
+        };
+        let Ok(timestamp) = timestamp.parse::<u64>() else {
+            continue;
+        };
+
+        let topic_id = *by_sanitized
+            .entry(topic.to_string())
+            .or_insert_with(|| topics::get_or_create_topic(topic).id);
+        // Indexed later by the search rebuild, not inside post_upgrade
+        storage::insert_snapshot_unindexed(topic_id, NewsSnapshot {
+            topic: topic.to_string(),
+            content,
+            timestamp,
+            status: None,
+            delta_summary: None,
+        });
+        if let Some(record) = topics::get_topic(topic_id) {
+            topics::record_update(topic_id, record.last_update.max(timestamp));
+        }
+        STORAGE.with(|storage| storage.borrow_mut().remove(&key));
+        migrated += 1;
+    }
+    migrated
+}
+
+// The single pre-provider API key was always a Groq key
+fn migrate_legacy_credentials() {
+    let Some(legacy) = LEGACY_CREDENTIALS.with(|cell| cell.borrow().get().clone()) else {
+        return;
+    };
+    CREDENTIALS.with(|credentials| {
+        let mut credentials = credentials.borrow_mut();
+        let groq = ProviderKind::Groq.name().to_string();
+        if !credentials.contains_key(&groq) {
+            credentials.insert(groq, legacy);
+        }
+    });
+    LEGACY_CREDENTIALS.with(|cell| {
+        cell.borrow_mut().set(None).expect("failed to clear legacy credentials");
+    });
+    ic_cdk::print("Migrated legacy Groq API key to per-provider credentials");
+}
+
+// Topics tracked before subscriptions existed have no owner, so the
+// canister itself becomes their subscriber. Earlier versions of this step
+// used the anonymous principal, which anyone signed out could unsubscribe;
+// those subscriptions move to the canister too.
+fn migrate_unowned_tracking() -> usize {
+    let owner = ic_cdk::id();
+    let now = ic_cdk::api::time();
+    let mut migrated = 0;
+    for (topic_id, subscribed_at) in subscriptions::topics_of(Principal::anonymous()) {
+        // Subscribe first so the topic is never briefly untracked
+        subscriptions::subscribe(topic_id, owner, subscribed_at);
+        subscriptions::unsubscribe(topic_id, Principal::anonymous());
+        migrated += 1;
+    }
+    let tracked: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    for topic_id in tracked {
+        if subscriptions::subscriber_count(topic_id) == 0 {
+            subscriptions::subscribe(topic_id, owner, now);
+            migrated += 1;
+        }
+    }
+    migrated
+}
+
+// The old storage-key encoding of a topic name
+fn sanitize_topic(topic: &str) -> String {
+    topic.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
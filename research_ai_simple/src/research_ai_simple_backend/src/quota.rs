@@ -0,0 +1,228 @@
+// Per-caller rate limits on endpoints that pay for an outcall. Each principal
+// gets a token bucket per endpoint; a call takes one token and is refused
+// while the bucket is empty. Admins are exempt. The anonymous principal
+// stands for every visitor of the public UI, so its shared bucket has a
+// limit of its own. Buckets are heap-only: an upgrade refills every bucket,
+// and buckets that have refilled are dropped since a missing one is full.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use crate::access::Role;
+use crate::config::{self, RateLimit};
+use crate::subscriptions;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// How often full buckets are swept out
+const PRUNE_INTERVAL_NANOS: u64 = NANOS_PER_HOUR;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    GetNews,
+    AgentQuery,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bucket {
+    tokens: u32,
+    // Time up to which refills have been credited
+    updated_at: u64,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: u64) -> Self {
+        Bucket { tokens: limit.capacity, updated_at: now }
+    }
+
+    // Credit whole tokens earned since `updated_at`, carrying the remainder
+    // over so frequent calls don't lose partial refills
+    fn refill(&mut self, limit: RateLimit, now: u64) {
+        self.tokens = self.tokens.min(limit.capacity);
+        if self.tokens == limit.capacity {
+            self.updated_at = now;
+            return;
+        }
+        let rate = limit.refill_per_hour as u128;
+        let earned = now.saturating_sub(self.updated_at) as u128 * rate / NANOS_PER_HOUR as u128;
+        if earned == 0 {
+            return;
+        }
+        let missing = (limit.capacity - self.tokens) as u128;
+        if earned >= missing {
+            self.tokens = limit.capacity;
+            self.updated_at = now;
+        } else {
+            self.tokens += earned as u32;
+            self.updated_at += (earned * NANOS_PER_HOUR as u128 / rate) as u64;
+        }
+    }
+
+    // When the next token arrives, if the bucket isn't full
+    fn next_token_at(&self, limit: RateLimit) -> Option<u64> {
+        if self.tokens >= limit.capacity {
+            None
+        } else {
+            Some(self.updated_at + NANOS_PER_HOUR.div_ceil(limit.refill_per_hour as u64))
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BucketStatus {
+    pub capacity: u32,
+    pub refill_per_hour: u32,
+    pub remaining: u32,
+    pub next_token_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct QuotaStatus {
+    // Admins are not rate limited
+    pub exempt: bool,
+    pub get_news: BucketStatus,
+    pub agent_query: BucketStatus,
+    pub tracked_topics: u32,
+    pub max_tracked_topics: u32,
+}
+
+thread_local! {
+    static BUCKETS: RefCell<BTreeMap<(Principal, Action), Bucket>> = RefCell::default();
+    static LAST_PRUNED: Cell<u64> = const { Cell::new(0) };
+}
+
+fn limit_for(principal: Principal, action: Action) -> RateLimit {
+    let rate_limits = config::limits().rate_limits();
+    if principal == Principal::anonymous() {
+        return rate_limits.anonymous();
+    }
+    match action {
+        Action::GetNews => rate_limits.get_news(),
+        Action::AgentQuery => rate_limits.agent_query(),
+    }
+}
+
+// Drop the buckets that have refilled completely, at most once an interval
+fn prune(now: u64) {
+    if now < LAST_PRUNED.with(|last| last.get()) + PRUNE_INTERVAL_NANOS {
+        return;
+    }
+    LAST_PRUNED.with(|last| last.set(now));
+    BUCKETS.with(|buckets| {
+        buckets.borrow_mut().retain(|&(principal, action), bucket| {
+            let limit = limit_for(principal, action);
+            bucket.refill(limit, now);
+            bucket.tokens < limit.capacity
+        });
+    });
+}
+
+fn bucket(principal: Principal, action: Action, limit: RateLimit, now: u64) -> Bucket {
+    let mut bucket = BUCKETS.with(|buckets| buckets.borrow().get(&(principal, action)).copied())
+        .unwrap_or_else(|| Bucket::full(limit, now));
+    bucket.refill(limit, now);
+    bucket
+}
+
+// Take a token for `action`. When the bucket is empty, returns when the
+// next token arrives instead.
+pub fn take(principal: Principal, role: Role, action: Action, now: u64) -> Result<(), u64> {
+    if role == Role::Admin {
+        return Ok(());
+    }
+    prune(now);
+    let limit = limit_for(principal, action);
+    let mut bucket = bucket(principal, action, limit, now);
+    if bucket.tokens == 0 {
+        return Err(bucket.next_token_at(limit).unwrap_or(now));
+    }
+    bucket.tokens -= 1;
+    BUCKETS.with(|buckets| buckets.borrow_mut().insert((principal, action), bucket));
+    Ok(())
+}
+
+// Whether `principal` may subscribe to one more topic
+pub fn can_subscribe(principal: Principal, role: Role) -> Result<(), u32> {
+    let max = config::limits().rate_limits().max_topics_per_principal();
+    if role == Role::Admin || (subscriptions::topics_of(principal).len() as u32) < max {
+        Ok(())
+    } else {
+        Err(max)
+    }
+}
+
+pub fn status(principal: Principal, role: Role, now: u64) -> QuotaStatus {
+    let bucket_status = |action| {
+        let limit = limit_for(principal, action);
+        let bucket = bucket(principal, action, limit, now);
+        BucketStatus {
+            capacity: limit.capacity,
+            refill_per_hour: limit.refill_per_hour,
+            remaining: bucket.tokens,
+            next_token_at: bucket.next_token_at(limit),
+        }
+    };
+    QuotaStatus {
+        exempt: role == Role::Admin,
+        get_news: bucket_status(Action::GetNews),
+        agent_query: bucket_status(Action::AgentQuery),
+        tracked_topics: subscriptions::topics_of(principal).len() as u32,
+        max_tracked_topics: config::limits().rate_limits().max_topics_per_principal(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit { capacity: 2, refill_per_hour: 4 };
+    const QUARTER_HOUR: u64 = NANOS_PER_HOUR / 4;
+
+    #[test]
+    fn empty_bucket_refills_one_token_per_interval() {
+        let mut bucket = Bucket { tokens: 0, updated_at: 0 };
+        assert_eq!(bucket.next_token_at(LIMIT), Some(QUARTER_HOUR));
+
+        bucket.refill(LIMIT, QUARTER_HOUR - 1);
+        assert_eq!(bucket.tokens, 0);
+
+        // Partial progress towards the next token is kept
+        bucket.refill(LIMIT, QUARTER_HOUR + QUARTER_HOUR / 2);
+        assert_eq!(bucket, Bucket { tokens: 1, updated_at: QUARTER_HOUR });
+        bucket.refill(LIMIT, 2 * QUARTER_HOUR);
+        assert_eq!(bucket.tokens, 2);
+        assert_eq!(bucket.next_token_at(LIMIT), None);
+
+        // Refills stop at capacity
+        bucket.refill(LIMIT, 10 * NANOS_PER_HOUR);
+        assert_eq!(bucket.tokens, 2);
+    }
+
+    #[test]
+    fn take_refuses_until_the_next_token() {
+        let caller = Principal::from_slice(&[7; 29]);
+        let limit = limit_for(caller, Action::AgentQuery);
+        for _ in 0..limit.capacity {
+            assert_eq!(take(caller, Role::Agent, Action::AgentQuery, 0), Ok(()));
+        }
+        let retry_at = take(caller, Role::Agent, Action::AgentQuery, 0).unwrap_err();
+        assert_eq!(retry_at, NANOS_PER_HOUR.div_ceil(limit.refill_per_hour as u64));
+        assert_eq!(take(caller, Role::Agent, Action::AgentQuery, retry_at), Ok(()));
+
+        // Other endpoints and admins are unaffected
+        assert_eq!(take(caller, Role::Agent, Action::GetNews, 0), Ok(()));
+        assert_eq!(take(caller, Role::Admin, Action::AgentQuery, 0), Ok(()));
+    }
+
+    #[test]
+    fn anonymous_callers_share_a_larger_bucket_and_full_buckets_are_dropped() {
+        let anonymous = limit_for(Principal::anonymous(), Action::GetNews);
+        assert!(anonymous.capacity > limit_for(Principal::from_slice(&[8; 29]), Action::GetNews).capacity);
+
+        let caller = Principal::from_slice(&[9; 29]);
+        assert_eq!(take(caller, Role::Reader, Action::GetNews, PRUNE_INTERVAL_NANOS), Ok(()));
+        assert!(BUCKETS.with(|buckets| buckets.borrow().contains_key(&(caller, Action::GetNews))));
+        // A day later the bucket has refilled and the next sweep drops it
+        prune(PRUNE_INTERVAL_NANOS + 24 * NANOS_PER_HOUR);
+        assert!(!BUCKETS.with(|buckets| buckets.borrow().contains_key(&(caller, Action::GetNews))));
+    }
+}
@@ -0,0 +1,287 @@
+// Snapshot retention. Recent history is kept in full; older history is
+// downsampled to the last successful snapshot per hour and then per day, and
+// each topic is capped at a maximum number of snapshots. A background timer
+// compacts a batch of topics every hour; `plan` backs both the timer and the
+// paged dry-run report.
+use candid::{CandidType, Deserialize};
+use ic_cdk_timers::set_timer_interval;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use crate::config;
+use crate::storage::{self, SNAPSHOTS, TOPICS};
+use crate::topics::TopicRecord;
+
+const COMPACTION_INTERVAL_SECS: u64 = 60 * 60;
+// Topics compacted per run; the next run carries on from there. Dry runs
+// report on as many per page.
+const TOPICS_PER_RUN: usize = 25;
+// Snapshots a dry-run page reads before it stops at the end of a topic
+const PREVIEW_SNAPSHOTS_PER_PAGE: u64 = 10_000;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+// Unset fields fall back to the canister-wide policy, then to the defaults
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RetentionPolicy {
+    // Every snapshot younger than this is kept (default 7)
+    pub keep_all_days: Option<u32>,
+    // Up to this age one snapshot per hour is kept, after it one per day
+    // (default 30)
+    pub keep_hourly_days: Option<u32>,
+    // Most snapshots kept per topic; the oldest go first (default 1000)
+    pub max_snapshots: Option<u32>,
+}
+
+impl RetentionPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(all), Some(hourly)) = (self.keep_all_days, self.keep_hourly_days) {
+            if hourly < all {
+                return Err("keep_hourly_days must not be below keep_all_days".to_string());
+            }
+        }
+        if self.max_snapshots == Some(0) {
+            return Err("max_snapshots must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    // This policy's settings with `fallback` filling the gaps
+    fn or(&self, fallback: &RetentionPolicy) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_all_days: self.keep_all_days.or(fallback.keep_all_days),
+            keep_hourly_days: self.keep_hourly_days.or(fallback.keep_hourly_days),
+            max_snapshots: self.max_snapshots.or(fallback.max_snapshots),
+        }
+    }
+
+    fn keep_all_nanos(&self) -> u64 {
+        self.keep_all_days.unwrap_or(7) as u64 * NANOS_PER_DAY
+    }
+
+    fn keep_hourly_nanos(&self) -> u64 {
+        (self.keep_hourly_days.unwrap_or(30) as u64 * NANOS_PER_DAY).max(self.keep_all_nanos())
+    }
+
+    fn max_snapshots(&self) -> usize {
+        self.max_snapshots.unwrap_or(1000) as usize
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RetentionReport {
+    pub topic_id: u64,
+    pub topic: String,
+    pub policy: RetentionPolicy,
+    pub snapshots: u64,
+    pub prunable: u64,
+    pub oldest_prunable: Option<u64>,
+    pub newest_prunable: Option<u64>,
+}
+
+// One page of a dry run
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RetentionPage {
+    pub reports: Vec<RetentionReport>,
+    // Pass back as `cursor` for the next page; unset on the last page
+    pub next_cursor: Option<u64>,
+}
+
+thread_local! {
+    // Topic id the next compaction run starts from
+    static NEXT_TOPIC: Cell<u64> = const { Cell::new(0) };
+}
+
+// A topic's own policy over the canister-wide one
+pub fn effective_policy(record: &TopicRecord) -> RetentionPolicy {
+    let global = config::get().retention.unwrap_or_default();
+    record.retention.as_ref().map_or(global.clone(), |own| own.or(&global))
+}
+
+// Timestamps to prune from a topic's snapshots, given as (timestamp,
+// successful) pairs in ascending order
+pub fn plan(snapshots: &[(u64, bool)], policy: &RetentionPolicy, now: u64) -> Vec<u64> {
+    let keep_all_after = now.saturating_sub(policy.keep_all_nanos());
+    let hourly_after = now.saturating_sub(policy.keep_hourly_nanos());
+
+    // Outside the keep-all window, the last successful snapshot of each
+    // bucket survives. Old failures never do.
+    let mut survivors: BTreeMap<(u64, u64), u64> = BTreeMap::new();
+    for &(timestamp, successful) in snapshots {
+        if timestamp >= keep_all_after || !successful {
+            continue;
+        }
+        let bucket = if timestamp >= hourly_after {
+            (NANOS_PER_HOUR, timestamp / NANOS_PER_HOUR)
+        } else {
+            (NANOS_PER_DAY, timestamp / NANOS_PER_DAY)
+        };
+        survivors.insert(bucket, timestamp);
+    }
+    let survivors: BTreeSet<u64> = survivors.into_values().collect();
+
+    let mut kept = Vec::new();
+    let mut pruned = Vec::new();
+    for &(timestamp, _) in snapshots {
+        if timestamp >= keep_all_after || survivors.contains(&timestamp) {
+            kept.push(timestamp);
+        } else {
+            pruned.push(timestamp);
+        }
+    }
+    // Over the cap, the oldest survivors go too
+    let excess = kept.len().saturating_sub(policy.max_snapshots());
+    pruned.extend_from_slice(&kept[..excess]);
+    pruned.sort_unstable();
+    pruned
+}
+
+fn topic_plan(record: &TopicRecord, now: u64) -> (RetentionPolicy, u64, Vec<u64>) {
+    let policy = effective_policy(record);
+    let snapshots: Vec<(u64, bool)> = SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((record.id, 0)..=(record.id, u64::MAX))
+            .map(|((_, timestamp), snapshot)| (timestamp, !snapshot.is_failure()))
+            .collect()
+    });
+    let pruned = plan(&snapshots, &policy, now);
+    (policy, snapshots.len() as u64, pruned)
+}
+
+// What compaction would prune from one topic right now
+pub fn report(record: TopicRecord, now: u64) -> RetentionReport {
+    let (policy, snapshots, pruned) = topic_plan(&record, now);
+    RetentionReport {
+        topic_id: record.id,
+        topic: record.display_name,
+        policy,
+        snapshots,
+        prunable: pruned.len() as u64,
+        oldest_prunable: pruned.first().copied(),
+        newest_prunable: pruned.last().copied(),
+    }
+}
+
+// Reports for the topics from id `cursor` on. Planning reads every snapshot
+// of a topic, so a page ends after TOPICS_PER_RUN topics or once
+// PREVIEW_SNAPSHOTS_PER_PAGE snapshots have been read, whichever is first.
+pub fn preview_page(cursor: u64, now: u64) -> RetentionPage {
+    let mut reports: Vec<RetentionReport> = Vec::new();
+    let mut read = 0;
+    let mut next_cursor = None;
+    TOPICS.with(|topics| {
+        for (id, record) in topics.borrow().range(cursor..) {
+            if reports.len() == TOPICS_PER_RUN || read >= PREVIEW_SNAPSHOTS_PER_PAGE {
+                next_cursor = Some(id);
+                break;
+            }
+            let report = report(record, now);
+            read += report.snapshots;
+            reports.push(report);
+        }
+    });
+    RetentionPage { reports, next_cursor }
+}
+
+// Compact the next batch of topics. Returns how many snapshots were pruned.
+pub fn compact(now: u64) -> u64 {
+    let start = NEXT_TOPIC.with(|next| next.get());
+    let mut batch: Vec<TopicRecord> = TOPICS.with(|topics| {
+        topics.borrow().range(start..).take(TOPICS_PER_RUN).map(|(_, record)| record).collect()
+    });
+    // Wrap around to the first topics once the end is reached
+    if batch.len() < TOPICS_PER_RUN && start > 0 {
+        let remaining = TOPICS_PER_RUN - batch.len();
+        batch.extend(TOPICS.with(|topics| {
+            topics.borrow().range(..start).take(remaining).map(|(_, record)| record).collect::<Vec<_>>()
+        }));
+    }
+    let next = batch.last().map_or(0, |record| record.id + 1);
+    NEXT_TOPIC.with(|cell| cell.set(next));
+
+    let mut pruned_total = 0;
+    for record in batch {
+        let (_, _, pruned) = topic_plan(&record, now);
+        for timestamp in &pruned {
+            storage::remove_snapshot(record.id, *timestamp);
+        }
+        pruned_total += pruned.len() as u64;
+    }
+    pruned_total
+}
+
+// Arm the hourly compaction timer. Like the scheduler's, it has to be armed
+// again after every upgrade.
+pub fn start() {
+    set_timer_interval(Duration::from_secs(COMPACTION_INTERVAL_SECS), || {
+        let pruned = compact(ic_cdk::api::time());
+        if pruned > 0 {
+            ic_cdk::print(format!("Retention: pruned {} old snapshots", pruned));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 100 * NANOS_PER_DAY;
+
+    fn ago(nanos: u64) -> u64 {
+        NOW - nanos
+    }
+
+    #[test]
+    fn keeps_recent_history_and_downsamples_older_snapshots() {
+        let minute = 60 * 1_000_000_000;
+        let snapshots = vec![
+            // 40 days old: two on one day, only the later one survives
+            (ago(40 * NANOS_PER_DAY + 2 * NANOS_PER_HOUR), true),
+            (ago(40 * NANOS_PER_DAY + NANOS_PER_HOUR), true),
+            // 10 days old: two in one hour plus a failure
+            (ago(10 * NANOS_PER_DAY + 30 * minute), true),
+            (ago(10 * NANOS_PER_DAY + 20 * minute), false),
+            (ago(10 * NANOS_PER_DAY + 10 * minute), true),
+            // Within the last week everything stays, failures included
+            (ago(NANOS_PER_DAY + 20 * minute), true),
+            (ago(NANOS_PER_DAY + 10 * minute), false),
+        ];
+        let pruned = plan(&snapshots, &RetentionPolicy::default(), NOW);
+        assert_eq!(pruned, vec![snapshots[0].0, snapshots[2].0, snapshots[3].0]);
+    }
+
+    #[test]
+    fn cap_prunes_the_oldest_survivors() {
+        let snapshots: Vec<(u64, bool)> = (1..=5).rev().map(|hour| (ago(hour * NANOS_PER_HOUR), true)).collect();
+        let policy = RetentionPolicy { max_snapshots: Some(3), ..Default::default() };
+        assert_eq!(plan(&snapshots, &policy, NOW), vec![snapshots[0].0, snapshots[1].0]);
+    }
+
+    #[test]
+    fn preview_pages_through_topics() {
+        for id in 1..=30 {
+            crate::topics::register_topic(id, &format!("topic {}", id), 0);
+        }
+        let first = preview_page(0, NOW);
+        assert_eq!(first.reports.len(), TOPICS_PER_RUN);
+        assert_eq!(first.next_cursor, Some(26));
+        let second = preview_page(26, NOW);
+        assert_eq!(second.reports.iter().map(|report| report.topic_id).collect::<Vec<_>>(), (26..=30).collect::<Vec<_>>());
+        assert_eq!(second.next_cursor, None);
+    }
+
+    #[test]
+    fn topic_policy_overrides_the_global_one_field_by_field() {
+        let global = RetentionPolicy { keep_all_days: Some(3), max_snapshots: Some(50), ..Default::default() };
+        let own = RetentionPolicy { max_snapshots: Some(10), ..Default::default() };
+        assert_eq!(
+            own.or(&global),
+            RetentionPolicy { keep_all_days: Some(3), keep_hourly_days: None, max_snapshots: Some(10) }
+        );
+        assert!(RetentionPolicy { keep_all_days: Some(10), keep_hourly_days: Some(5), max_snapshots: None }
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// Per-topic refresh scheduling. The canister runs one short periodic tick;
+// each tick refreshes only the tracked topics whose next-due time has passed,
+// so every topic can have its own cadence.
+use candid::{CandidType, Deserialize};
+use ic_cdk_timers::{clear_timer, set_timer_interval, TimerId};
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::config;
+use crate::costs;
+use crate::cycle;
+use crate::storage::{NEXT_DUE, TRACKED_TOPICS};
+use crate::topics::TopicRecord;
+use crate::watchdog::{self, CyclesLevel};
+
+// How often the scheduler wakes up to look for due topics
+pub const TICK_SECS: u64 = 60;
+// Cadence for topics that never set their own, unless the install args
+// chose another
+pub const DEFAULT_INTERVAL_SECS: u64 = 1200;
+// Bounds on a topic's cadence: no finer than a tick, at least once a week
+pub const MIN_INTERVAL_SECS: u64 = TICK_SECS;
+pub const MAX_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+// Longest a failing topic is backed off, unless its own interval is longer
+pub const MAX_BACKOFF_SECS: u64 = 24 * 60 * 60;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// Timers live on the heap and are dropped by every upgrade, so this state is
+// deliberately not stable: `start` has to run again in post_upgrade.
+#[derive(Default)]
+struct TimerState {
+    timer: Option<TimerId>,
+    armed_at: u64,
+    last_tick: Option<u64>,
+}
+
+thread_local! {
+    static TIMER_STATE: RefCell<TimerState> = RefCell::default();
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SchedulerStatus {
+    pub timer_armed: bool,
+    pub cycle_running: bool,
+    // Today's outcall spend has reached the daily budget; ticks are skipped
+    // until the next UTC day
+    pub budget_exhausted: bool,
+    pub tick_secs: u64,
+    pub default_interval_secs: u64,
+    pub last_tick: Option<u64>,
+    // When the tick timer fires next; unset when no timer is armed
+    pub next_tick: Option<u64>,
+    pub tracked_topics: u64,
+    pub due_topics: u64,
+    // Earliest next-due time across tracked topics
+    pub next_topic_due: Option<u64>,
+    pub max_refreshes_per_tick: u32,
+}
+
+// Arm the tick timer, replacing any timer already armed
+pub fn start(on_tick: fn()) {
+    let timer = set_timer_interval(Duration::from_secs(TICK_SECS), move || {
+        TIMER_STATE.with(|state| state.borrow_mut().last_tick = Some(ic_cdk::api::time()));
+        on_tick();
+    });
+    TIMER_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(previous) = state.timer.replace(timer) {
+            clear_timer(previous);
+        }
+        state.armed_at = ic_cdk::api::time();
+    });
+}
+
+pub fn status() -> SchedulerStatus {
+    let now = ic_cdk::api::time();
+    let tick_nanos = TICK_SECS * NANOS_PER_SEC;
+    let (timer_armed, armed_at, last_tick) = TIMER_STATE.with(|state| {
+        let state = state.borrow();
+        (state.timer.is_some(), state.armed_at, state.last_tick)
+    });
+    let next_tick = timer_armed.then(|| {
+        // Interval timers fire at armed_at + k * tick
+        let elapsed = now.saturating_sub(armed_at);
+        armed_at + (elapsed / tick_nanos + 1) * tick_nanos
+    });
+
+    let tracked: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    let due_times: Vec<u64> = tracked.iter().map(|id| next_due(*id)).collect();
+
+    SchedulerStatus {
+        timer_armed,
+        cycle_running: cycle::is_running(),
+        budget_exhausted: costs::budget_exhausted(now),
+        tick_secs: TICK_SECS,
+        default_interval_secs: default_interval_secs(),
+        last_tick,
+        next_tick,
+        tracked_topics: tracked.len() as u64,
+        due_topics: due_times.iter().filter(|due| **due <= now).count() as u64,
+        next_topic_due: due_times.into_iter().min(),
+        max_refreshes_per_tick: config::limits().max_refreshes_per_tick,
+    }
+}
+
+pub fn validate_interval(interval_secs: u64) -> Result<(), String> {
+    if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&interval_secs) {
+        return Err(format!(
+            "update interval must be between {} and {} seconds",
+            MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+        ));
+    }
+    Ok(())
+}
+
+pub fn default_interval_secs() -> u64 {
+    config::get().default_interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS)
+}
+
+pub fn interval_secs(record: &TopicRecord) -> u64 {
+    record.update_interval_secs.unwrap_or_else(default_interval_secs)
+}
+
+// Next time (ns) a tracked topic is due. Topics tracked before scheduling
+// existed have no entry and are due immediately.
+pub fn next_due(topic_id: u64) -> u64 {
+    NEXT_DUE.with(|due| due.borrow().get(&topic_id)).unwrap_or(0)
+}
+
+// Push a topic's next refresh one interval past `from` (ns)
+pub fn reschedule(record: &TopicRecord, from: u64) {
+    schedule_after(record.id, interval_secs(record), from);
+}
+
+// Delay before retrying a topic after `failures` consecutive failures: its
+// interval, doubled for every failure
+pub fn backoff_secs(record: &TopicRecord, failures: u32) -> u64 {
+    let interval = interval_secs(record);
+    let backoff = interval.saturating_mul(1u64.checked_shl(failures).unwrap_or(u64::MAX));
+    backoff.min(MAX_BACKOFF_SECS.max(interval))
+}
+
+// Push a failing topic's next refresh out by its backoff
+pub fn reschedule_with_backoff(record: &TopicRecord, failures: u32, from: u64) {
+    schedule_after(record.id, backoff_secs(record, failures), from);
+}
+
+// While the watchdog reports low cycles every delay is stretched
+fn schedule_after(topic_id: u64, delay_secs: u64, from: u64) {
+    let delay_secs = if watchdog::current_level() == CyclesLevel::Low {
+        delay_secs.saturating_mul(watchdog::REDUCED_CADENCE_FACTOR)
+    } else {
+        delay_secs
+    };
+    let next = from.saturating_add(delay_secs.saturating_mul(NANOS_PER_SEC));
+    NEXT_DUE.with(|due| due.borrow_mut().insert(topic_id, next));
+}
+
+// Retry a topic at a fixed time, e.g. when its provider's circuit reopens
+pub fn schedule_at(topic_id: u64, due_at: u64) {
+    NEXT_DUE.with(|due| due.borrow_mut().insert(topic_id, due_at));
+}
+
+pub fn unschedule(topic_id: u64) {
+    NEXT_DUE.with(|due| due.borrow_mut().remove(&topic_id));
+}
+
+// Tracked topics due at `now`, most overdue first, capped at the per-tick
+// refresh limit
+pub fn due_topics(now: u64) -> Vec<u64> {
+    let tracked: Vec<u64> = TRACKED_TOPICS.with(|topics| {
+        topics.borrow().iter().map(|(id, _)| id).collect()
+    });
+    let mut due: Vec<(u64, u64)> = tracked.into_iter()
+        .map(|id| (next_due(id), id))
+        .filter(|(next, _)| *next <= now)
+        .collect();
+    due.sort_unstable();
+    due.into_iter()
+        .take(config::limits().max_refreshes_per_tick as usize)
+        .map(|(_, id)| id)
+        .collect()
+}
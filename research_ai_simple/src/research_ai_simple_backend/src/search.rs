@@ -0,0 +1,472 @@
+// Full-text search over stored news. Each successful snapshot is tokenized
+// into lowercased alphanumeric words with stopwords dropped, and its term
+// frequencies go into an inverted index keyed by term hash. Queries are
+// ranked with BM25 and return a snippet around the first match. Snapshots
+// stored before the index existed are indexed in batches from a timer.
+use candid::{CandidType, Deserialize};
+use ic_cdk_timers::set_timer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use crate::dedupe::fnv1a;
+use crate::storage::{
+    impl_candid_storable, NewsSnapshot, SEARCH_DOC_LENGTHS, SEARCH_POSTINGS, SEARCH_TOTALS, SNAPSHOTS,
+};
+use crate::topics;
+
+// BM25 term frequency saturation and length normalization
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+const MIN_TERM_CHARS: usize = 2;
+const MAX_TERM_CHARS: usize = 40;
+// Distinct words of a query that are searched for
+const MAX_QUERY_TERMS: usize = 10;
+const DEFAULT_PAGE_SIZE: usize = 10;
+const MAX_PAGE_SIZE: usize = 50;
+// Words of context kept before the first match, and snippet length in words
+const SNIPPET_LEAD_WORDS: usize = 8;
+const SNIPPET_WORDS: usize = 30;
+// Snapshots read per rebuild step; the next step runs in a new message
+const REBUILD_BATCH: usize = 500;
+
+// Sorted, for binary search
+const STOPWORDS: &[&str] = &[
+    "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "been",
+    "before", "but", "by", "can", "could", "did", "do", "does", "for", "from", "had", "has",
+    "have", "he", "her", "his", "how", "if", "in", "into", "is", "it", "its", "more", "most",
+    "no", "not", "of", "on", "or", "our", "over", "she", "so", "such", "than", "that", "the",
+    "their", "them", "then", "there", "these", "they", "this", "those", "to", "up", "was",
+    "we", "were", "what", "when", "where", "which", "while", "who", "will", "with", "would",
+    "you", "your",
+];
+
+// Running totals over every indexed snapshot, and how far a rebuild of the
+// index has got
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct IndexTotals {
+    pub documents: u64,
+    pub terms: u64,
+    // Key of the next snapshot to index while a rebuild is under way
+    pub rebuild_cursor: Option<(u64, u64)>,
+    // Set once every stored snapshot has been indexed
+    pub complete: Option<bool>,
+}
+
+impl_candid_storable!(IndexTotals);
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SearchFilters {
+    // Names or aliases; every topic when unset
+    pub topics: Option<Vec<String>>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SearchPage {
+    pub offset: Option<u32>,
+    // Default 10, at most 50
+    pub limit: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SearchHit {
+    pub topic_id: u64,
+    pub topic: String,
+    pub timestamp: u64,
+    pub score: f64,
+    // Excerpt around the first match, with matched words wrapped in **
+    pub snippet: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    // Matching snapshots across all pages
+    pub total: u64,
+    pub next_offset: Option<u32>,
+    // False while older snapshots are still being indexed, so hits may be
+    // missing
+    pub index_complete: bool,
+}
+
+// Byte ranges of the alphanumeric runs in `text`
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+// The indexed form of a word, or None for stopwords and words too short or
+// too long to be useful
+fn term(word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    let chars = word.chars().count();
+    if !(MIN_TERM_CHARS..=MAX_TERM_CHARS).contains(&chars) || STOPWORDS.binary_search(&word.as_str()).is_ok() {
+        return None;
+    }
+    Some(word)
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    word_spans(text).into_iter().filter_map(|(start, end)| term(&text[start..end])).collect()
+}
+
+fn term_hash(term: &str) -> u64 {
+    fnv1a(term.as_bytes())
+}
+
+fn totals() -> IndexTotals {
+    SEARCH_TOTALS.with(|totals| totals.borrow().get().clone())
+}
+
+fn update_totals(f: impl FnOnce(&mut IndexTotals)) {
+    SEARCH_TOTALS.with(|totals| {
+        let mut totals = totals.borrow_mut();
+        let mut value = totals.get().clone();
+        f(&mut value);
+        totals.set(value).expect("failed to store search index totals");
+    });
+}
+
+// Add a snapshot to the index. Failed fetches have no content to index.
+pub fn index(topic_id: u64, snapshot: &NewsSnapshot) {
+    if snapshot.is_failure() {
+        return;
+    }
+    let terms = tokenize(&snapshot.content);
+    if terms.is_empty() {
+        return;
+    }
+    let mut frequencies: BTreeMap<u64, u32> = BTreeMap::new();
+    for term in &terms {
+        *frequencies.entry(term_hash(term)).or_default() += 1;
+    }
+    SEARCH_POSTINGS.with(|postings| {
+        let mut postings = postings.borrow_mut();
+        for (hash, frequency) in frequencies {
+            postings.insert((hash, topic_id, snapshot.timestamp), frequency);
+        }
+    });
+    SEARCH_DOC_LENGTHS.with(|lengths| {
+        lengths.borrow_mut().insert((topic_id, snapshot.timestamp), terms.len() as u32)
+    });
+    update_totals(|totals| {
+        totals.documents += 1;
+        totals.terms += terms.len() as u64;
+    });
+}
+
+// Remove a snapshot from the index; a no-op if it was never indexed
+pub fn unindex(topic_id: u64, snapshot: &NewsSnapshot) {
+    let Some(length) = SEARCH_DOC_LENGTHS.with(|lengths| {
+        lengths.borrow_mut().remove(&(topic_id, snapshot.timestamp))
+    }) else {
+        return;
+    };
+    let hashes: BTreeSet<u64> = tokenize(&snapshot.content).iter().map(|term| term_hash(term)).collect();
+    SEARCH_POSTINGS.with(|postings| {
+        let mut postings = postings.borrow_mut();
+        for hash in hashes {
+            postings.remove(&(hash, topic_id, snapshot.timestamp));
+        }
+    });
+    update_totals(|totals| {
+        totals.documents = totals.documents.saturating_sub(1);
+        totals.terms = totals.terms.saturating_sub(length as u64);
+    });
+}
+
+pub fn is_empty() -> bool {
+    SEARCH_DOC_LENGTHS.with(|lengths| lengths.borrow().is_empty())
+}
+
+pub fn is_complete() -> bool {
+    totals().rebuild_cursor.is_none()
+}
+
+// Whether a rebuild under way has yet to reach the snapshot at `key`
+pub fn rebuild_pending(key: (u64, u64)) -> bool {
+    totals().rebuild_cursor.is_some_and(|cursor| key >= cursor)
+}
+
+// Start indexing every stored snapshot, unless the index already covers
+// them. Returns whether a rebuild was started.
+pub fn start_rebuild() -> bool {
+    let totals = totals();
+    if totals.complete == Some(true) || totals.rebuild_cursor.is_some() {
+        return false;
+    }
+    // Earlier versions built a non-empty index in one go
+    if !is_empty() {
+        update_totals(|totals| totals.complete = Some(true));
+        return false;
+    }
+    update_totals(|totals| totals.rebuild_cursor = Some((0, 0)));
+    true
+}
+
+// Index up to `batch` snapshots from the rebuild cursor on and move it
+// past them; the rebuild is done once the cursor is cleared. Returns how
+// many snapshots were read.
+pub fn rebuild_step(batch: usize) -> usize {
+    let Some(cursor) = totals().rebuild_cursor else {
+        return 0;
+    };
+    let mut snapshots: Vec<((u64, u64), NewsSnapshot)> = SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow().range(cursor..).take(batch + 1).collect()
+    });
+    let next = if snapshots.len() > batch {
+        snapshots.pop().map(|(key, _)| key)
+    } else {
+        None
+    };
+    for (key, snapshot) in &snapshots {
+        // Snapshots stored since the rebuild started were indexed on the way in
+        if SEARCH_DOC_LENGTHS.with(|lengths| !lengths.borrow().contains_key(key)) {
+            index(key.0, snapshot);
+        }
+    }
+    update_totals(|totals| {
+        totals.rebuild_cursor = next;
+        if next.is_none() {
+            totals.complete = Some(true);
+        }
+    });
+    snapshots.len()
+}
+
+// Run the remaining rebuild steps, one timer per batch. Like the other
+// timers, this has to be armed again after every upgrade.
+pub fn resume_rebuild() {
+    if is_complete() {
+        return;
+    }
+    set_timer(Duration::ZERO, || {
+        rebuild_step(REBUILD_BATCH);
+        if is_complete() {
+            ic_cdk::print("Search index rebuilt");
+        }
+        resume_rebuild();
+    });
+}
+
+pub fn search(query: &str, filters: &SearchFilters, page: &SearchPage) -> Result<SearchResults, String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in tokenize(query) {
+        if !terms.contains(&term) && terms.len() < MAX_QUERY_TERMS {
+            terms.push(term);
+        }
+    }
+    if terms.is_empty() {
+        return Err("Query has no searchable words".to_string());
+    }
+    let topic_ids: Option<BTreeSet<u64>> = match &filters.topics {
+        Some(names) => Some(names.iter()
+            .map(|name| topics::resolve_topic(name)
+                .map(|record| record.id)
+                .ok_or_else(|| format!("Unknown topic: {}", name)))
+            .collect::<Result<_, _>>()?),
+        None => None,
+    };
+    let since = filters.since.unwrap_or(0);
+    let until = filters.until.unwrap_or(u64::MAX);
+
+    let totals = totals();
+    let documents = totals.documents as f64;
+    let average_length = if totals.documents == 0 {
+        1.0
+    } else {
+        totals.terms as f64 / documents
+    };
+
+    let mut scores: BTreeMap<(u64, u64), f64> = BTreeMap::new();
+    for term in &terms {
+        let hash = term_hash(term);
+        let postings: Vec<((u64, u64, u64), u32)> = SEARCH_POSTINGS.with(|postings| {
+            postings.borrow().range((hash, 0, 0)..=(hash, u64::MAX, u64::MAX)).collect()
+        });
+        // Document frequency counts every topic and time, filters or not
+        let frequency = postings.len() as f64;
+        let idf = (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln();
+        for ((_, topic_id, timestamp), term_frequency) in postings {
+            if timestamp < since
+                || timestamp > until
+                || topic_ids.as_ref().is_some_and(|ids| !ids.contains(&topic_id))
+            {
+                continue;
+            }
+            let length = SEARCH_DOC_LENGTHS.with(|lengths| lengths.borrow().get(&(topic_id, timestamp)))
+                .unwrap_or(0) as f64;
+            let term_frequency = term_frequency as f64;
+            let score = idf * term_frequency * (K1 + 1.0)
+                / (term_frequency + K1 * (1.0 - B + B * length / average_length));
+            *scores.entry((topic_id, timestamp)).or_default() += score;
+        }
+    }
+
+    let mut ranked: Vec<((u64, u64), f64)> = scores.into_iter().collect();
+    // Best first; newer news wins a tie
+    ranked.sort_by(|(a_key, a_score), (b_key, b_score)| {
+        b_score.total_cmp(a_score).then(b_key.1.cmp(&a_key.1))
+    });
+
+    let offset = page.offset.unwrap_or(0) as usize;
+    let limit = page.limit.map_or(DEFAULT_PAGE_SIZE, |limit| (limit as usize).clamp(1, MAX_PAGE_SIZE));
+    let terms: BTreeSet<String> = terms.into_iter().collect();
+    let hits: Vec<SearchHit> = ranked.iter()
+        .skip(offset)
+        .take(limit)
+        .filter_map(|&((topic_id, timestamp), score)| {
+            let snapshot = SNAPSHOTS.with(|snapshots| snapshots.borrow().get(&(topic_id, timestamp)))?;
+            let topic = topics::get_topic(topic_id)
+                .map_or_else(|| snapshot.topic.clone(), |record| record.display_name);
+            Some(SearchHit {
+                topic_id,
+                topic,
+                timestamp,
+                score,
+                snippet: snippet(&snapshot.content, &terms),
+            })
+        })
+        .collect();
+    let next = offset + limit;
+    Ok(SearchResults {
+        hits,
+        total: ranked.len() as u64,
+        next_offset: (next < ranked.len()).then_some(next as u32),
+        index_complete: totals.rebuild_cursor.is_none(),
+    })
+}
+
+// A window of words around the first matching one, matches in **bold**
+pub fn snippet(content: &str, terms: &BTreeSet<String>) -> String {
+    let spans = word_spans(content);
+    if spans.is_empty() {
+        return String::new();
+    }
+    let matches = |&(start, end): &(usize, usize)| {
+        term(&content[start..end]).is_some_and(|term| terms.contains(&term))
+    };
+    let first = spans.iter().position(matches).unwrap_or(0);
+    let from = first.saturating_sub(SNIPPET_LEAD_WORDS);
+    let to = (from + SNIPPET_WORDS).min(spans.len());
+
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    let mut position = spans[from].0;
+    for span in &spans[from..to] {
+        snippet.push_str(&content[position..span.0]);
+        let word = &content[span.0..span.1];
+        if matches(span) {
+            snippet.push_str("**");
+            snippet.push_str(word);
+            snippet.push_str("**");
+        } else {
+            snippet.push_str(word);
+        }
+        position = span.1;
+    }
+    // Keep trailing punctuation up to the next word
+    let tail_end = spans.get(to).map_or(content.len(), |span| span.0);
+    snippet.push_str(content[position..tail_end].trim_end());
+    if to < spans.len() {
+        snippet.push('…');
+    }
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{insert_snapshot, insert_snapshot_unindexed, remove_snapshot, test_snapshot, SNAPSHOTS};
+
+    #[test]
+    fn tokenizes_lowercased_words_without_stopwords() {
+        assert!(STOPWORDS.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            tokenize("The Fed's rate-cut is ON hold, at 5%!"),
+            vec!["fed", "rate", "cut", "hold"]
+        );
+    }
+
+    #[test]
+    fn ranks_by_bm25_and_tracks_snapshot_writes() {
+        insert_snapshot(1, test_snapshot(10, "Solar output rose. Solar panels got cheaper and solar farms grew."));
+        insert_snapshot(1, test_snapshot(20, "Wind farms expanded while solar stalled."));
+        insert_snapshot(2, test_snapshot(15, "Oil prices fell on weak demand."));
+
+        let everything = SearchPage::default();
+        let results = search("solar farms", &SearchFilters::default(), &everything).unwrap();
+        assert_eq!(results.total, 2);
+        let order: Vec<u64> = results.hits.iter().map(|hit| hit.timestamp).collect();
+        assert_eq!(order, vec![10, 20]);
+        assert!(results.hits[0].score > results.hits[1].score);
+
+        let recent = SearchFilters { since: Some(12), ..Default::default() };
+        let results = search("solar", &recent, &everything).unwrap();
+        assert_eq!(results.hits.iter().map(|hit| hit.timestamp).collect::<Vec<_>>(), vec![20]);
+
+        remove_snapshot(1, 10);
+        insert_snapshot(2, test_snapshot(15, "Oil prices fell as solar demand grew."));
+        let results = search("solar", &SearchFilters::default(), &everything).unwrap();
+        assert_eq!(results.total, 2);
+        assert!(results.hits.iter().all(|hit| hit.timestamp != 10));
+        assert!(search("the and", &SearchFilters::default(), &everything).is_err());
+    }
+
+    #[test]
+    fn rebuild_indexes_older_snapshots_in_batches() {
+        // Stored without going through the index, as before it existed
+        for timestamp in [1, 2, 3] {
+            SNAPSHOTS.with(|snapshots| {
+                snapshots.borrow_mut().insert((1, timestamp), test_snapshot(timestamp, "solar news"))
+            });
+        }
+        assert!(start_rebuild());
+        // Migrated ahead of the cursor, so left for the rebuild to index
+        insert_snapshot_unindexed(2, test_snapshot(1, "solar news"));
+        assert_eq!(totals().documents, 0);
+        assert_eq!(rebuild_step(2), 2);
+        let results = search("solar", &SearchFilters::default(), &SearchPage::default()).unwrap();
+        assert_eq!((results.total, results.index_complete), (2, false));
+
+        // Stored mid-rebuild, so already indexed when the cursor gets there
+        insert_snapshot(1, test_snapshot(4, "solar news"));
+        assert_eq!(rebuild_step(2), 2);
+        assert_eq!(rebuild_step(2), 1);
+        let results = search("solar", &SearchFilters::default(), &SearchPage::default()).unwrap();
+        assert_eq!((results.total, results.index_complete), (5, true));
+        assert_eq!(totals().documents, 5);
+        assert!(!start_rebuild());
+
+        // With no rebuild left to do, bulk writes are indexed straight away
+        insert_snapshot_unindexed(3, test_snapshot(9, "solar news"));
+        assert_eq!(totals().documents, 6);
+    }
+
+    #[test]
+    fn snippet_highlights_matches_around_the_first_one() {
+        let terms: BTreeSet<String> = ["solar".to_string()].into();
+        let content = format!("{} Solar output rose, solar farms grew.", "word ".repeat(20));
+        let snippet = snippet(&content, &terms);
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with("**Solar** output rose, **solar** farms grew."), "{}", snippet);
+    }
+}
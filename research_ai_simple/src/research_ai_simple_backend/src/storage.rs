@@ -0,0 +1,432 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::access::RoleGrant;
+use crate::config::CanisterConfig;
+use crate::credentials::ProviderCredentials;
+use crate::cycle::CycleReport;
+use crate::digests::Digest;
+use crate::search::{self, IndexTotals};
+use crate::topics::TopicRecord;
+use crate::watchdog::AlertEvent;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Snapshots are ordered by (topic_id, timestamp), so one topic's history is
+// a contiguous key range.
+pub type SnapshotKey = (u64, u64);
+
+// Digests are ordered by (topic_id, period, period_start)
+pub type DigestKey = (u64, u8, u64);
+
+// Search postings are ordered by (term hash, topic_id, timestamp), so one
+// term's postings are a contiguous key range.
+pub type PostingKey = (u64, u64, u64);
+
+// Stable memory regions, one per structure. Never reuse or renumber an ID:
+// existing canisters have data laid out under these.
+const STORAGE_MEMORY_ID: MemoryId = MemoryId::new(0);
+// Retired: tracking and last-update times keyed by raw topic string
+const LEGACY_TRACKED_TOPICS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const LEGACY_LAST_UPDATE_MEMORY_ID: MemoryId = MemoryId::new(2);
+const SNAPSHOTS_MEMORY_ID: MemoryId = MemoryId::new(3);
+const TOPIC_INDEX_MEMORY_ID: MemoryId = MemoryId::new(4);
+const NEXT_TOPIC_ID_MEMORY_ID: MemoryId = MemoryId::new(5);
+const TOPICS_MEMORY_ID: MemoryId = MemoryId::new(6);
+const TRACKED_TOPICS_MEMORY_ID: MemoryId = MemoryId::new(7);
+// Retired: single Groq key from before per-provider credentials
+const LEGACY_CREDENTIALS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const CREDENTIALS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(10);
+const NEXT_DUE_MEMORY_ID: MemoryId = MemoryId::new(11);
+const CYCLE_REPORTS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const COST_BY_TOPIC_MEMORY_ID: MemoryId = MemoryId::new(13);
+const COST_BY_CALLER_MEMORY_ID: MemoryId = MemoryId::new(14);
+const COST_BY_DAY_MEMORY_ID: MemoryId = MemoryId::new(15);
+const ALERTS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const SUBSCRIBERS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const SUBSCRIPTIONS_MEMORY_ID: MemoryId = MemoryId::new(18);
+const ROLES_MEMORY_ID: MemoryId = MemoryId::new(19);
+const DIGESTS_MEMORY_ID: MemoryId = MemoryId::new(20);
+const SEARCH_POSTINGS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const SEARCH_DOC_LENGTHS_MEMORY_ID: MemoryId = MemoryId::new(22);
+const SEARCH_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const SCHEDULED_COST_BY_DAY_MEMORY_ID: MemoryId = MemoryId::new(24);
+
+// Candid-encoded, unbounded Storable impl for a record type. Adding `opt`
+// fields to such a record later stays backward compatible with stored data.
+macro_rules! impl_candid_storable {
+    ($ty:ty) => {
+        impl ic_stable_structures::Storable for $ty {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                std::borrow::Cow::Owned(
+                    candid::encode_one(self).expect(concat!("failed to encode ", stringify!($ty)))
+                )
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                candid::decode_one(bytes.as_ref())
+                    .expect(concat!("failed to decode ", stringify!($ty)))
+            }
+
+            const BOUND: ic_stable_structures::storable::Bound =
+                ic_stable_structures::storable::Bound::Unbounded;
+        }
+    };
+}
+pub(crate) use impl_candid_storable;
+
+// A single stored news result for a topic
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NewsSnapshot {
+    pub topic: String,
+    // Empty for failed fetches
+    pub content: String,
+    pub timestamp: u64,
+    // Unset on snapshots written before failures were recorded, which were
+    // all successful
+    pub status: Option<SnapshotStatus>,
+    // LLM summary of what changed since the previous successful snapshot,
+    // when delta summaries are enabled
+    pub delta_summary: Option<String>,
+}
+
+impl_candid_storable!(NewsSnapshot);
+
+impl NewsSnapshot {
+    pub fn status(&self) -> SnapshotStatus {
+        self.status.clone().unwrap_or(SnapshotStatus::Ok)
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(self.status, Some(SnapshotStatus::Failed { .. }))
+    }
+}
+
+// A successful snapshot for tests anywhere in the crate
+#[cfg(test)]
+pub fn test_snapshot(timestamp: u64, content: &str) -> NewsSnapshot {
+    NewsSnapshot {
+        topic: "topic".to_string(),
+        content: content.to_string(),
+        timestamp,
+        status: Some(SnapshotStatus::Ok),
+        delta_summary: None,
+    }
+}
+
+// Outcome of the fetch that produced a snapshot
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SnapshotStatus {
+    Ok,
+    // `code` is the outcall rejection code, or the provider error class when
+    // the outcall itself went through
+    Failed { code: String, message: String },
+}
+
+// Storage structures. Everything lives directly in stable memory, so the
+// whole knowledge base survives canister upgrades without any serialization
+// step in pre_upgrade.
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // Legacy key/value storage used by the agent endpoints
+    pub static STORAGE: RefCell<StableBTreeMap<String, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(STORAGE_MEMORY_ID)))
+    );
+
+    // News history, keyed by (topic_id, timestamp)
+    pub static SNAPSHOTS: RefCell<StableBTreeMap<SnapshotKey, NewsSnapshot, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SNAPSHOTS_MEMORY_ID)))
+    );
+
+    // Daily and weekly rollups of each topic's snapshots
+    pub static DIGESTS: RefCell<StableBTreeMap<DigestKey, Digest, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(DIGESTS_MEMORY_ID)))
+    );
+
+    // Full-text index: term frequency of each term in each indexed snapshot
+    pub static SEARCH_POSTINGS: RefCell<StableBTreeMap<PostingKey, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_POSTINGS_MEMORY_ID)))
+    );
+
+    // Indexed terms per snapshot, for BM25 length normalization
+    pub static SEARCH_DOC_LENGTHS: RefCell<StableBTreeMap<SnapshotKey, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_DOC_LENGTHS_MEMORY_ID)))
+    );
+
+    pub static SEARCH_TOTALS: RefCell<StableCell<IndexTotals, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(SEARCH_TOTALS_MEMORY_ID)), IndexTotals::default())
+            .expect("failed to initialize search index totals")
+    );
+
+    // Topic registry: topic_id -> record
+    pub static TOPICS: RefCell<StableBTreeMap<u64, TopicRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TOPICS_MEMORY_ID)))
+    );
+
+    // Normalized topic name or alias -> topic_id
+    pub static TOPIC_INDEX: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TOPIC_INDEX_MEMORY_ID)))
+    );
+
+    pub static NEXT_TOPIC_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(NEXT_TOPIC_ID_MEMORY_ID)), 1)
+            .expect("failed to initialize topic id counter")
+    );
+
+    // Topic subscriptions, indexed both ways: (topic_id, principal) and
+    // (principal, topic_id) -> subscribed_at
+    pub static SUBSCRIBERS: RefCell<StableBTreeMap<(u64, Principal), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SUBSCRIBERS_MEMORY_ID)))
+    );
+
+    pub static SUBSCRIPTIONS: RefCell<StableBTreeMap<(Principal, u64), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SUBSCRIPTIONS_MEMORY_ID)))
+    );
+
+    // Topics with at least one subscriber, refreshed periodically
+    pub static TRACKED_TOPICS: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRACKED_TOPICS_MEMORY_ID)))
+    );
+
+    // Tracked topic_id -> time (ns) its next scheduled refresh is due
+    pub static NEXT_DUE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(NEXT_DUE_MEMORY_ID)))
+    );
+
+    // Recent update cycle reports by cycle id
+    pub static CYCLE_REPORTS: RefCell<StableBTreeMap<u64, CycleReport, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CYCLE_REPORTS_MEMORY_ID)))
+    );
+
+    // Cycles spent on outcalls: per topic_id, per caller, per UTC day
+    pub static COST_BY_TOPIC: RefCell<StableBTreeMap<u64, u128, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(COST_BY_TOPIC_MEMORY_ID)))
+    );
+
+    pub static COST_BY_CALLER: RefCell<StableBTreeMap<Principal, u128, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(COST_BY_CALLER_MEMORY_ID)))
+    );
+
+    pub static COST_BY_DAY: RefCell<StableBTreeMap<u64, u128, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(COST_BY_DAY_MEMORY_ID)))
+    );
+
+    // The part of each day's spend made by the canister's own timers, which
+    // the daily budget caps
+    pub static SCHEDULED_COST_BY_DAY: RefCell<StableBTreeMap<u64, u128, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SCHEDULED_COST_BY_DAY_MEMORY_ID)))
+    );
+
+    // Low-cycles watchdog alerts by event id
+    pub static ALERTS: RefCell<StableBTreeMap<u64, AlertEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ALERTS_MEMORY_ID)))
+    );
+
+    // LLM provider API keys by provider name. Never returned by any endpoint.
+    pub static CREDENTIALS: RefCell<StableBTreeMap<String, ProviderCredentials, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CREDENTIALS_MEMORY_ID)))
+    );
+
+    // Role grants by principal. Kept by clear_storage so admins can't lock
+    // themselves out.
+    pub static ROLES: RefCell<StableBTreeMap<Principal, RoleGrant, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ROLES_MEMORY_ID)))
+    );
+
+    // Canister-wide settings
+    pub static CONFIG: RefCell<StableCell<CanisterConfig, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY_ID)), CanisterConfig::default())
+            .expect("failed to initialize canister config")
+    );
+
+    // Only read by migrations from versions that keyed topics by raw string
+    pub static LEGACY_TRACKED_TOPICS: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(LEGACY_TRACKED_TOPICS_MEMORY_ID)))
+    );
+
+    pub static LEGACY_LAST_UPDATE: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(LEGACY_LAST_UPDATE_MEMORY_ID)))
+    );
+
+    pub static LEGACY_CREDENTIALS: RefCell<StableCell<Option<ProviderCredentials>, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(LEGACY_CREDENTIALS_MEMORY_ID)), None)
+            .expect("failed to initialize legacy provider credentials")
+    );
+}
+
+// Every snapshot write and removal goes through these, so the search index
+// stays in step with the history
+pub fn insert_snapshot(topic_id: u64, snapshot: NewsSnapshot) {
+    remove_snapshot(topic_id, snapshot.timestamp);
+    search::index(topic_id, &snapshot);
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow_mut().insert((topic_id, snapshot.timestamp), snapshot);
+    });
+}
+
+// For bulk writes during an upgrade: when a search index rebuild will reach
+// the snapshot anyway, indexing is left to its timer batches
+pub fn insert_snapshot_unindexed(topic_id: u64, snapshot: NewsSnapshot) {
+    if !search::rebuild_pending((topic_id, snapshot.timestamp)) {
+        return insert_snapshot(topic_id, snapshot);
+    }
+    remove_snapshot(topic_id, snapshot.timestamp);
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow_mut().insert((topic_id, snapshot.timestamp), snapshot);
+    });
+}
+
+pub fn remove_snapshot(topic_id: u64, timestamp: u64) -> Option<NewsSnapshot> {
+    let removed = SNAPSHOTS.with(|snapshots| snapshots.borrow_mut().remove(&(topic_id, timestamp)))?;
+    search::unindex(topic_id, &removed);
+    Some(removed)
+}
+
+// Most recent snapshot for a topic, skipping failed fetches unless asked
+pub fn latest_snapshot(topic_id: u64, include_failures: bool) -> Option<NewsSnapshot> {
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((topic_id, 0)..=(topic_id, u64::MAX))
+            .rev()
+            .map(|(_, snapshot)| snapshot)
+            .find(|snapshot| include_failures || !snapshot.is_failure())
+    })
+}
+
+// The latest successful snapshot if it, or a refresh that found it
+// unchanged at `last_checked`, is at most `max_age_secs` old
+pub fn fresh_snapshot(topic_id: u64, last_checked: u64, max_age_secs: u64, now: u64) -> Option<NewsSnapshot> {
+    latest_snapshot(topic_id, false).filter(|snapshot| {
+        let checked_at = snapshot.timestamp.max(last_checked);
+        now.saturating_sub(checked_at) <= max_age_secs.saturating_mul(1_000_000_000)
+    })
+}
+
+// Up to `limit` snapshots of a topic with timestamps in `from..=to`, oldest
+// or newest first, and whether the range holds more
+pub fn snapshot_range(
+    topic_id: u64,
+    from: u64,
+    to: u64,
+    descending: bool,
+    limit: usize,
+    include_failures: bool,
+) -> (Vec<NewsSnapshot>, bool) {
+    if from > to {
+        return (Vec::new(), false);
+    }
+    SNAPSHOTS.with(|snapshots| {
+        let snapshots = snapshots.borrow();
+        let range = snapshots.range((topic_id, from)..=(topic_id, to));
+        let matching = |(_, snapshot): &(SnapshotKey, NewsSnapshot)| {
+            include_failures || !snapshot.is_failure()
+        };
+        // One extra entry tells whether there is another page
+        let mut page: Vec<NewsSnapshot> = if descending {
+            range.rev().filter(matching).take(limit + 1).map(|(_, s)| s).collect()
+        } else {
+            range.filter(matching).take(limit + 1).map(|(_, s)| s).collect()
+        };
+        let has_more = page.len() > limit;
+        page.truncate(limit);
+        (page, has_more)
+    })
+}
+
+// Snapshots of a topic with timestamps in `from..=to`
+pub fn count_snapshots(topic_id: u64, from: u64, to: u64, include_failures: bool) -> u64 {
+    if from > to {
+        return 0;
+    }
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((topic_id, from)..=(topic_id, to))
+            .filter(|(_, snapshot)| include_failures || !snapshot.is_failure())
+            .count() as u64
+    })
+}
+
+// Up to `limit` snapshots for a topic, newest first
+pub fn snapshot_history(topic_id: u64, limit: usize, include_failures: bool) -> Vec<NewsSnapshot> {
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((topic_id, 0)..=(topic_id, u64::MAX))
+            .rev()
+            .map(|(_, snapshot)| snapshot)
+            .filter(|snapshot| include_failures || !snapshot.is_failure())
+            .take(limit)
+            .collect()
+    })
+}
+
+// Number of successful snapshots for a topic
+pub fn snapshot_count(topic_id: u64) -> u64 {
+    SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((topic_id, 0)..=(topic_id, u64::MAX))
+            .filter(|(_, snapshot)| !snapshot.is_failure())
+            .count() as u64
+    })
+}
+
+pub fn clear_all() {
+    STORAGE.with(|storage| storage.borrow_mut().clear_new());
+    SNAPSHOTS.with(|snapshots| snapshots.borrow_mut().clear_new());
+    DIGESTS.with(|digests| digests.borrow_mut().clear_new());
+    SEARCH_POSTINGS.with(|postings| postings.borrow_mut().clear_new());
+    SEARCH_DOC_LENGTHS.with(|lengths| lengths.borrow_mut().clear_new());
+    SEARCH_TOTALS.with(|totals| {
+        // Nothing is left unindexed, so no rebuild is needed
+        let empty = IndexTotals { complete: Some(true), ..Default::default() };
+        totals.borrow_mut().set(empty).expect("failed to reset search index totals");
+    });
+    TOPICS.with(|topics| topics.borrow_mut().clear_new());
+    TOPIC_INDEX.with(|index| index.borrow_mut().clear_new());
+    TRACKED_TOPICS.with(|topics| topics.borrow_mut().clear_new());
+    NEXT_DUE.with(|due| due.borrow_mut().clear_new());
+    SUBSCRIBERS.with(|subs| subs.borrow_mut().clear_new());
+    SUBSCRIPTIONS.with(|subs| subs.borrow_mut().clear_new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(timestamp: u64, failed: bool) -> NewsSnapshot {
+        let mut snapshot = test_snapshot(timestamp, &format!("news {}", timestamp));
+        if failed {
+            snapshot.status = Some(SnapshotStatus::Failed { code: "http_500".to_string(), message: String::new() });
+        }
+        snapshot
+    }
+
+    fn timestamps(page: &[NewsSnapshot]) -> Vec<u64> {
+        page.iter().map(|snapshot| snapshot.timestamp).collect()
+    }
+
+    #[test]
+    fn snapshot_range_pages_in_either_order() {
+        for timestamp in 1..=6 {
+            insert_snapshot(1, snapshot(timestamp, timestamp == 4));
+        }
+        insert_snapshot(2, snapshot(3, false));
+
+        let (page, has_more) = snapshot_range(1, 0, u64::MAX, true, 2, false);
+        assert_eq!((timestamps(&page), has_more), (vec![6, 5], true));
+        let (page, has_more) = snapshot_range(1, 0, 4, true, 2, false);
+        assert_eq!((timestamps(&page), has_more), (vec![3, 2], true));
+        let (page, has_more) = snapshot_range(1, 0, 1, true, 2, false);
+        assert_eq!((timestamps(&page), has_more), (vec![1], false));
+
+        let (page, has_more) = snapshot_range(1, 2, 5, false, 10, true);
+        assert_eq!((timestamps(&page), has_more), (vec![2, 3, 4, 5], false));
+        assert_eq!(count_snapshots(1, 2, 5, true), 4);
+        assert_eq!(count_snapshots(1, 2, 5, false), 3);
+        assert_eq!(count_snapshots(1, 5, 2, false), 0);
+    }
+}
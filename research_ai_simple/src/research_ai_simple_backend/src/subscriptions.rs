@@ -0,0 +1,84 @@
+// Who tracks which topic. A topic is tracked (and refreshed by the
+// scheduler) for as long as at least one principal is subscribed to it;
+// each principal can only add or remove its own subscription.
+use candid::Principal;
+
+use crate::scheduler;
+use crate::storage::{SUBSCRIBERS, SUBSCRIPTIONS, TRACKED_TOPICS};
+
+// Lowest principal in key order, for ranging over one topic's subscribers
+fn min_principal() -> Principal {
+    Principal::from_slice(&[])
+}
+
+// Subscribe `principal` to a topic, which starts tracking it if it wasn't
+// already. Returns false if the principal was already subscribed.
+pub fn subscribe(topic_id: u64, principal: Principal, now: u64) -> bool {
+    if SUBSCRIBERS.with(|subs| subs.borrow().contains_key(&(topic_id, principal))) {
+        return false;
+    }
+    SUBSCRIBERS.with(|subs| subs.borrow_mut().insert((topic_id, principal), now));
+    SUBSCRIPTIONS.with(|subs| subs.borrow_mut().insert((principal, topic_id), now));
+    TRACKED_TOPICS.with(|topics| topics.borrow_mut().insert(topic_id, ()));
+    true
+}
+
+// Drop `principal`'s subscription. The topic stops being tracked once its
+// last subscriber leaves. Returns false if there was no such subscription.
+pub fn unsubscribe(topic_id: u64, principal: Principal) -> bool {
+    if SUBSCRIBERS.with(|subs| subs.borrow_mut().remove(&(topic_id, principal))).is_none() {
+        return false;
+    }
+    SUBSCRIPTIONS.with(|subs| subs.borrow_mut().remove(&(principal, topic_id)));
+    if subscriber_count(topic_id) == 0 {
+        TRACKED_TOPICS.with(|topics| topics.borrow_mut().remove(&topic_id));
+        scheduler::unschedule(topic_id);
+    }
+    true
+}
+
+pub fn is_subscribed(topic_id: u64, principal: Principal) -> bool {
+    SUBSCRIBERS.with(|subs| subs.borrow().contains_key(&(topic_id, principal)))
+}
+
+// Subscribers of a topic with when they subscribed
+pub fn subscribers(topic_id: u64) -> Vec<(Principal, u64)> {
+    SUBSCRIBERS.with(|subs| {
+        subs.borrow()
+            .range((topic_id, min_principal())..)
+            .take_while(|((id, _), _)| *id == topic_id)
+            .map(|((_, principal), subscribed_at)| (principal, subscribed_at))
+            .collect()
+    })
+}
+
+pub fn subscriber_count(topic_id: u64) -> u64 {
+    subscribers(topic_id).len() as u64
+}
+
+// Topics `principal` is subscribed to with when it subscribed
+pub fn topics_of(principal: Principal) -> Vec<(u64, u64)> {
+    SUBSCRIPTIONS.with(|subs| {
+        subs.borrow()
+            .range((principal, 0)..=(principal, u64::MAX))
+            // Bounded keys are zero-padded, so principals differing only in
+            // trailing zero bytes share this range
+            .filter(|((owner, _), _)| *owner == principal)
+            .map(|((_, topic_id), subscribed_at)| (topic_id, subscribed_at))
+            .collect()
+    })
+}
+
+// Hand every subscription on `source` over to `target`, keeping the
+// earlier subscription time where a principal had both
+pub fn move_subscriptions(source: u64, target: u64) {
+    for (principal, subscribed_at) in subscribers(source) {
+        SUBSCRIBERS.with(|subs| subs.borrow_mut().remove(&(source, principal)));
+        SUBSCRIPTIONS.with(|subs| subs.borrow_mut().remove(&(principal, source)));
+
+        let subscribed_at = SUBSCRIBERS.with(|subs| subs.borrow().get(&(target, principal)))
+            .map_or(subscribed_at, |existing| existing.min(subscribed_at));
+        SUBSCRIBERS.with(|subs| subs.borrow_mut().insert((target, principal), subscribed_at));
+        SUBSCRIPTIONS.with(|subs| subs.borrow_mut().insert((principal, target), subscribed_at));
+    }
+}
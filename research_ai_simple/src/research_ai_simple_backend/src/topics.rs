@@ -0,0 +1,302 @@
+use candid::{CandidType, Deserialize};
+use llm_providers::{LlmError, ProviderConfig};
+
+use crate::digests;
+use crate::retention::RetentionPolicy;
+use crate::scheduler;
+use crate::subscriptions;
+use crate::storage::{
+    self, impl_candid_storable, NEXT_TOPIC_ID, SNAPSHOTS, TOPICS, TOPIC_INDEX, TRACKED_TOPICS,
+};
+
+// A registered topic. `name` is the canonical normalized form used for
+// lookups; every alias resolves to the same `id`, and all history and counts
+// are keyed by that id alone.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TopicRecord {
+    pub id: u64,
+    pub name: String,
+    pub display_name: String,
+    pub aliases: Vec<String>,
+    pub created_at: u64,
+    pub last_update: u64,
+    // Overrides the canister-wide provider for this topic
+    pub provider: Option<ProviderConfig>,
+    // Most recent failed refresh; cleared by the next successful one
+    pub last_error: Option<TopicFailure>,
+    // Seconds between scheduled refreshes; the scheduler default when unset
+    pub update_interval_secs: Option<u64>,
+    // Failed refreshes since the last successful one; unset means none
+    pub consecutive_failures: Option<u32>,
+    // Last successful refresh, including ones that found nothing new
+    pub last_checked: Option<u64>,
+    // Successful refreshes, including ones that found nothing new
+    pub check_count: Option<u32>,
+    // Overrides parts of the canister-wide retention policy for this topic
+    pub retention: Option<RetentionPolicy>,
+}
+
+impl TopicRecord {
+    pub fn failures(&self) -> u32 {
+        self.consecutive_failures.unwrap_or(0)
+    }
+
+    // Topics from before checks were counted were last checked when last
+    // updated
+    pub fn last_checked(&self) -> u64 {
+        self.last_checked.unwrap_or(self.last_update)
+    }
+
+    pub fn check_count(&self) -> u32 {
+        self.check_count.unwrap_or(0)
+    }
+}
+
+impl_candid_storable!(TopicRecord);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TopicFailure {
+    pub error: LlmError,
+    pub failed_at: u64,
+}
+
+// Canonical lookup form of a topic: trimmed, lowercased, inner whitespace
+// collapsed. Punctuation is kept, so "C++" and "C#" stay distinct topics.
+pub fn normalize_topic(topic: &str) -> String {
+    topic.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Longest topic name or alias accepted, in characters
+const MAX_TOPIC_CHARS: usize = 200;
+
+pub fn validate_topic(topic: &str) -> Result<(), String> {
+    if topic.trim().is_empty() {
+        return Err("Topic must not be empty".to_string());
+    }
+    if topic.chars().count() > MAX_TOPIC_CHARS {
+        return Err(format!("Topic must be at most {} characters", MAX_TOPIC_CHARS));
+    }
+    Ok(())
+}
+
+// Display form of a topic: the caller's spelling with whitespace tidied up
+fn display_topic(topic: &str) -> String {
+    topic.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn get_topic(topic_id: u64) -> Option<TopicRecord> {
+    TOPICS.with(|topics| topics.borrow().get(&topic_id))
+}
+
+// Find the topic a name or alias refers to, without registering anything
+pub fn resolve_topic(topic: &str) -> Option<TopicRecord> {
+    let id = TOPIC_INDEX.with(|index| index.borrow().get(&normalize_topic(topic)))?;
+    get_topic(id)
+}
+
+// Find the topic a name or alias refers to, registering it on first use
+pub fn get_or_create_topic(topic: &str) -> TopicRecord {
+    if let Some(record) = resolve_topic(topic) {
+        return record;
+    }
+
+    let id = NEXT_TOPIC_ID.with(|next| {
+        let mut next = next.borrow_mut();
+        let id = *next.get();
+        next.set(id + 1).expect("failed to advance topic id counter");
+        id
+    });
+    register_topic(id, topic, ic_cdk::api::time())
+}
+
+// Insert a record under a known id. Only `get_or_create_topic` and the
+// upgrade migrations should call this.
+pub fn register_topic(id: u64, topic: &str, created_at: u64) -> TopicRecord {
+    let record = TopicRecord {
+        id,
+        name: normalize_topic(topic),
+        display_name: display_topic(topic),
+        aliases: Vec::new(),
+        created_at,
+        last_update: 0,
+        provider: None,
+        last_error: None,
+        update_interval_secs: None,
+        consecutive_failures: None,
+        last_checked: None,
+        check_count: None,
+        retention: None,
+    };
+    TOPICS.with(|topics| topics.borrow_mut().insert(id, record.clone()));
+    TOPIC_INDEX.with(|index| index.borrow_mut().insert(record.name.clone(), id));
+    record
+}
+
+pub fn record_update(topic_id: u64, timestamp: u64) {
+    TOPICS.with(|topics| {
+        let mut topics = topics.borrow_mut();
+        if let Some(mut record) = topics.get(&topic_id) {
+            record.last_update = timestamp;
+            record.last_error = None;
+            record.consecutive_failures = None;
+            topics.insert(topic_id, record);
+        }
+    });
+}
+
+// A successful refresh. `changed` is false when the news matched the last
+// snapshot and nothing new was stored.
+pub fn record_check(topic_id: u64, timestamp: u64, changed: bool) {
+    TOPICS.with(|topics| {
+        let mut topics = topics.borrow_mut();
+        if let Some(mut record) = topics.get(&topic_id) {
+            if changed {
+                record.last_update = timestamp;
+            }
+            record.last_checked = Some(timestamp);
+            record.check_count = Some(record.check_count().saturating_add(1));
+            record.last_error = None;
+            record.consecutive_failures = None;
+            topics.insert(topic_id, record);
+        }
+    });
+}
+
+// Returns the topic's consecutive failure count including this one
+pub fn record_failure(topic_id: u64, error: LlmError, failed_at: u64) -> u32 {
+    TOPICS.with(|topics| {
+        let mut topics = topics.borrow_mut();
+        let Some(mut record) = topics.get(&topic_id) else {
+            return 0;
+        };
+        let failures = record.failures().saturating_add(1);
+        record.last_error = Some(TopicFailure { error, failed_at });
+        record.consecutive_failures = Some(failures);
+        topics.insert(topic_id, record);
+        failures
+    })
+}
+
+pub fn set_retention(topic: &str, retention: Option<RetentionPolicy>) -> Result<TopicRecord, String> {
+    if let Some(retention) = &retention {
+        retention.validate()?;
+    }
+    let mut record = resolve_topic(topic)
+        .ok_or_else(|| format!("Unknown topic: {}", topic))?;
+    record.retention = retention;
+    TOPICS.with(|topics| topics.borrow_mut().insert(record.id, record.clone()));
+    Ok(record)
+}
+
+pub fn set_provider(topic: &str, provider: Option<ProviderConfig>) -> Result<TopicRecord, String> {
+    let mut record = resolve_topic(topic)
+        .ok_or_else(|| format!("Unknown topic: {}", topic))?;
+    record.provider = provider;
+    TOPICS.with(|topics| topics.borrow_mut().insert(record.id, record.clone()));
+    Ok(record)
+}
+
+// Change how often a topic is refreshed. A tracked topic's next refresh
+// moves to one new interval after its last update.
+pub fn set_update_interval(topic: &str, interval_secs: Option<u64>) -> Result<TopicRecord, String> {
+    if let Some(interval_secs) = interval_secs {
+        scheduler::validate_interval(interval_secs)?;
+    }
+    let mut record = resolve_topic(topic)
+        .ok_or_else(|| format!("Unknown topic: {}", topic))?;
+    record.update_interval_secs = interval_secs;
+    TOPICS.with(|topics| topics.borrow_mut().insert(record.id, record.clone()));
+    if is_tracked(record.id) {
+        scheduler::reschedule(&record, record.last_checked());
+    }
+    Ok(record)
+}
+
+pub fn is_tracked(topic_id: u64) -> bool {
+    TRACKED_TOPICS.with(|topics| topics.borrow().contains_key(&topic_id))
+}
+
+// Point `alias` at an existing topic. Fails if the alias already names a
+// different topic; use `merge_topics` to combine two topics with history.
+pub fn add_alias(topic: &str, alias: &str) -> Result<TopicRecord, String> {
+    validate_topic(alias)?;
+    let mut record = resolve_topic(topic)
+        .ok_or_else(|| format!("Unknown topic: {}", topic))?;
+    let alias = normalize_topic(alias);
+
+    match TOPIC_INDEX.with(|index| index.borrow().get(&alias)) {
+        Some(id) if id == record.id => return Ok(record),
+        Some(_) => return Err(format!(
+            "'{}' is already a separate topic; merge it instead", alias
+        )),
+        None => {}
+    }
+
+    TOPIC_INDEX.with(|index| index.borrow_mut().insert(alias.clone(), record.id));
+    record.aliases.push(alias);
+    TOPICS.with(|topics| topics.borrow_mut().insert(record.id, record.clone()));
+    Ok(record)
+}
+
+// Fold `source` into `target`: its history moves over, its name and aliases
+// become aliases of `target`, and tracking carries across.
+pub fn merge_topics(source: &str, target: &str) -> Result<TopicRecord, String> {
+    let source = resolve_topic(source)
+        .ok_or_else(|| format!("Unknown topic: {}", source))?;
+    let mut target = resolve_topic(target)
+        .ok_or_else(|| format!("Unknown topic: {}", target))?;
+    if source.id == target.id {
+        return Err("Cannot merge a topic into itself".to_string());
+    }
+
+    let moved: Vec<u64> = SNAPSHOTS.with(|snapshots| {
+        snapshots.borrow()
+            .range((source.id, 0)..=(source.id, u64::MAX))
+            .map(|((_, timestamp), _)| timestamp)
+            .collect()
+    });
+    for timestamp in moved {
+        let Some(mut snapshot) = storage::remove_snapshot(source.id, timestamp) else {
+            continue;
+        };
+        // Never overwrite the target's own history on a timestamp tie
+        while SNAPSHOTS.with(|snapshots| snapshots.borrow().contains_key(&(target.id, snapshot.timestamp))) {
+            snapshot.timestamp += 1;
+        }
+        storage::insert_snapshot(target.id, snapshot);
+    }
+
+    let names: Vec<String> = std::iter::once(source.name.clone())
+        .chain(source.aliases.iter().cloned())
+        .collect();
+    TOPIC_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for name in &names {
+            index.insert(name.clone(), target.id);
+        }
+    });
+    target.aliases.extend(names);
+    target.last_update = target.last_update.max(source.last_update);
+    target.last_checked = Some(target.last_checked().max(source.last_checked()));
+    target.check_count = Some(target.check_count().saturating_add(source.check_count()));
+
+    let source_tracked = TRACKED_TOPICS.with(|topics| {
+        topics.borrow_mut().remove(&source.id).is_some()
+    });
+    scheduler::unschedule(source.id);
+    subscriptions::move_subscriptions(source.id, target.id);
+    digests::move_digests(source.id, target.id);
+    if source_tracked && !is_tracked(target.id) {
+        TRACKED_TOPICS.with(|topics| topics.borrow_mut().insert(target.id, ()));
+        scheduler::reschedule(&target, target.last_checked());
+    }
+    TOPICS.with(|topics| {
+        let mut topics = topics.borrow_mut();
+        topics.remove(&source.id);
+        topics.insert(target.id, target.clone());
+    });
+    Ok(target)
+}
@@ -0,0 +1,117 @@
+// Low-cycles watchdog. Every scheduler tick checks the canister balance
+// first: below the low threshold topics refresh at a reduced cadence, below
+// the critical threshold scheduled refreshes stop altogether, so the canister
+// never spends itself into freezing. Level changes are kept as alert events.
+use candid::{CandidType, Deserialize};
+use std::cell::Cell;
+
+use crate::config;
+use crate::storage::{impl_candid_storable, ALERTS};
+
+// Intervals are stretched by this factor while cycles are low
+pub const REDUCED_CADENCE_FACTOR: u64 = 4;
+// Alert events kept in stable memory; older ones are dropped
+const MAX_STORED_ALERTS: u64 = 100;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CyclesLevel {
+    Normal,
+    // Refreshing at a reduced cadence
+    Low,
+    // Scheduled refreshes paused
+    Critical,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    pub level: CyclesLevel,
+    pub previous_level: CyclesLevel,
+    pub balance: u128,
+    pub message: String,
+}
+
+impl_candid_storable!(AlertEvent);
+
+thread_local! {
+    // Level seen by the last check. Starts Normal after an upgrade, so a
+    // canister that is still low raises a fresh alert on the first tick.
+    static LAST_LEVEL: Cell<CyclesLevel> = const { Cell::new(CyclesLevel::Normal) };
+}
+
+pub fn level_for(balance: u128) -> CyclesLevel {
+    let limits = config::limits();
+    if balance < limits.critical_cycles_threshold() {
+        CyclesLevel::Critical
+    } else if balance < limits.low_cycles_threshold() {
+        CyclesLevel::Low
+    } else {
+        CyclesLevel::Normal
+    }
+}
+
+pub fn current_level() -> CyclesLevel {
+    level_for(ic_cdk::api::canister_balance128())
+}
+
+// Check the balance and record an alert if the level changed since the
+// last check. Returns the current level.
+pub fn check() -> CyclesLevel {
+    let balance = ic_cdk::api::canister_balance128();
+    let level = level_for(balance);
+    let previous_level = LAST_LEVEL.with(|last| last.replace(level));
+    if level != previous_level {
+        record_alert(level, previous_level, balance);
+    }
+    level
+}
+
+fn record_alert(level: CyclesLevel, previous_level: CyclesLevel, balance: u128) {
+    let message = match level {
+        CyclesLevel::Normal => "Cycles balance recovered: normal refresh cadence resumed".to_string(),
+        CyclesLevel::Low => format!(
+            "Cycles balance low: refresh intervals stretched {}x",
+            REDUCED_CADENCE_FACTOR
+        ),
+        CyclesLevel::Critical => "Cycles balance critical: scheduled refreshes paused".to_string(),
+    };
+    ic_cdk::print(format!("Watchdog: {} (balance {})", message, balance));
+
+    ALERTS.with(|alerts| {
+        let mut alerts = alerts.borrow_mut();
+        let id = alerts.last_key_value().map(|(id, _)| id + 1).unwrap_or(1);
+        alerts.insert(id, AlertEvent {
+            id,
+            timestamp: ic_cdk::api::time(),
+            level,
+            previous_level,
+            balance,
+            message,
+        });
+        while alerts.len() > MAX_STORED_ALERTS {
+            alerts.pop_first();
+        }
+    });
+}
+
+// Up to `limit` alert events, newest first
+pub fn recent_alerts(limit: usize) -> Vec<AlertEvent> {
+    ALERTS.with(|alerts| {
+        alerts.borrow().iter().rev().take(limit).map(|(_, alert)| alert).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_follow_the_default_thresholds() {
+        let limits = config::limits();
+        assert_eq!(level_for(limits.low_cycles_threshold()), CyclesLevel::Normal);
+        assert_eq!(level_for(limits.low_cycles_threshold() - 1), CyclesLevel::Low);
+        assert_eq!(level_for(limits.critical_cycles_threshold()), CyclesLevel::Low);
+        assert_eq!(level_for(limits.critical_cycles_threshold() - 1), CyclesLevel::Critical);
+    }
+}
@@ -0,0 +1,119 @@
+#!/bin/bash
+
+# Upgrade persistence test: deploys the canister, writes news and agent data,
+# upgrades the canister in place and checks everything can still be read back
+# and that the update timer is running again.
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+BLUE='\033[0;34m'
+CYAN='\033[0;36m'
+NC='\033[0m' # No Color
+
+CANISTER_NAME="research_ai_simple_backend"
+TEST_TOPIC="upgrade persistence check"
+TEST_KEY="upgrade_test_marker"
+TEST_VALUE="written before upgrade at $(date +%s)"
+
+print_step() {
+    echo -e "${BLUE}=== $1 ===${NC}"
+}
+
+print_success() {
+    echo -e "${GREEN}✓ $1${NC}"
+}
+
+print_info() {
+    echo -e "${CYAN}→ $1${NC}"
+}
+
+print_error() {
+    echo -e "${RED}✗ $1${NC}"
+}
+
+fail() {
+    print_error "$1"
+    exit 1
+}
+
+if ! command -v dfx &> /dev/null; then
+    fail "DFX not found. Please install DFX first."
+fi
+
+if [ ! -f "dfx.json" ]; then
+    fail "dfx.json not found. Please run this script from the research_ai_simple directory."
+fi
+
+print_step "Starting Local ICP Replica"
+if ! dfx ping local &> /dev/null; then
+    dfx start --clean --background || fail "Failed to start local replica"
+fi
+print_success "Local replica is running"
+
+print_step "Deploying Canister"
+dfx deploy $CANISTER_NAME || fail "Initial deployment failed"
+print_success "Canister deployed"
+
+print_step "Writing Data Before Upgrade"
+# The mock provider answers without an outcall, so the test needs no API key
+dfx canister call $CANISTER_NAME set_provider_config '(record {
+  kind = variant { Mock };
+  model = "mock";
+  max_tokens = 300;
+  temperature = 0.0;
+  base_url = null
+})' > /dev/null || fail "set_provider_config failed"
+
+dfx canister call $CANISTER_NAME agent_store_data "(record {
+  key = \"$TEST_KEY\";
+  value = \"$TEST_VALUE\";
+  agent_id = \"upgrade-test\"
+})" || fail "agent_store_data failed"
+
+dfx canister call $CANISTER_NAME get_news "(record {
+  topic = \"$TEST_TOPIC\";
+  enable_tracking = true
+})" > /dev/null || fail "get_news failed"
+
+DATA_BEFORE=$(dfx canister call $CANISTER_NAME agent_get_data "(\"$TEST_KEY\")")
+NEWS_BEFORE=$(dfx canister call $CANISTER_NAME get_latest_stored_news "(\"$TEST_TOPIC\")")
+TRACKED_BEFORE=$(dfx canister call $CANISTER_NAME get_tracked_topics)
+
+[[ "$DATA_BEFORE" == *"$TEST_VALUE"* ]] || fail "Agent data was not stored: $DATA_BEFORE"
+[[ "$NEWS_BEFORE" == *"opt record"* ]] || fail "News was not stored: $NEWS_BEFORE"
+print_success "News and agent data written"
+
+print_step "Upgrading Canister"
+dfx deploy $CANISTER_NAME --upgrade-unchanged || fail "Upgrade failed"
+print_success "Canister upgraded"
+
+print_step "Reading Data After Upgrade"
+DATA_AFTER=$(dfx canister call $CANISTER_NAME agent_get_data "(\"$TEST_KEY\")")
+NEWS_AFTER=$(dfx canister call $CANISTER_NAME get_latest_stored_news "(\"$TEST_TOPIC\")")
+TRACKED_AFTER=$(dfx canister call $CANISTER_NAME get_tracked_topics)
+
+[ "$DATA_BEFORE" == "$DATA_AFTER" ] || fail "Agent data changed across upgrade:
+before: $DATA_BEFORE
+after:  $DATA_AFTER"
+print_success "Agent data survived the upgrade"
+
+[ "$NEWS_BEFORE" == "$NEWS_AFTER" ] || fail "Stored news changed across upgrade:
+before: $NEWS_BEFORE
+after:  $NEWS_AFTER"
+print_success "Stored news survived the upgrade"
+
+[ "$TRACKED_BEFORE" == "$TRACKED_AFTER" ] || fail "Tracked topics changed across upgrade:
+before: $TRACKED_BEFORE
+after:  $TRACKED_AFTER"
+print_success "Tracked topics survived the upgrade"
+
+SCHEDULER_AFTER=$(dfx canister call $CANISTER_NAME get_scheduler_status)
+[[ "$SCHEDULER_AFTER" == *"timer_armed = true"* ]] || fail "Update timer was not re-armed after upgrade: $SCHEDULER_AFTER"
+print_success "Update timer re-armed after the upgrade"
+
+print_info "Cleaning up test data"
+dfx canister call $CANISTER_NAME untrack_topic "(\"$TEST_TOPIC\")" > /dev/null
+
+echo
+print_success "Upgrade persistence test passed"